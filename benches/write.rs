@@ -71,5 +71,37 @@ fn benchmark(c: &mut Criterion) {
     });
 }
 
-criterion::criterion_group!(write_benches, benchmark);
+fn broadcast(c: &mut Criterion) {
+    // Writes the same 4 KiB binary message to 1k sockets then flushes them
+    let message = Message::Binary(vec![0x2a; 4096]);
+    let mut sockets: Vec<_> = (0..1_000)
+        .map(|_| {
+            WebSocket::from_raw_socket(
+                MockWrite(Vec::with_capacity(MOCK_WRITE_LEN)),
+                tungstenite::protocol::Role::Server,
+                None,
+            )
+        })
+        .collect();
+
+    c.bench_function("broadcast 4 KiB to 1k sockets, clone", |b| {
+        b.iter(|| {
+            for ws in &mut sockets {
+                ws.write(message.clone()).unwrap();
+                ws.flush().unwrap();
+            }
+        });
+    });
+
+    c.bench_function("broadcast 4 KiB to 1k sockets, write_ref", |b| {
+        b.iter(|| {
+            for ws in &mut sockets {
+                ws.write_ref(&message).unwrap();
+                ws.flush().unwrap();
+            }
+        });
+    });
+}
+
+criterion::criterion_group!(write_benches, benchmark, broadcast);
 criterion::criterion_main!(write_benches);
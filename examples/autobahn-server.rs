@@ -4,7 +4,10 @@ use std::{
 };
 
 use log::*;
-use tungstenite::{accept, handshake::HandshakeRole, Error, HandshakeError, Message, Result};
+use tungstenite::{
+    accept_with_config, handshake::HandshakeRole, protocol::WebSocketConfig, Error, HandshakeError,
+    Message, Result,
+};
 
 fn must_not_block<Role: HandshakeRole>(err: HandshakeError<Role>) -> Error {
     match err {
@@ -14,7 +17,8 @@ fn must_not_block<Role: HandshakeRole>(err: HandshakeError<Role>) -> Error {
 }
 
 fn handle_client(stream: TcpStream) -> Result<()> {
-    let mut socket = accept(stream).map_err(must_not_block)?;
+    let config = WebSocketConfig { close_on_error: true, ..WebSocketConfig::default() };
+    let mut socket = accept_with_config(stream, Some(config)).map_err(must_not_block)?;
     info!("Running test");
     loop {
         match socket.read()? {
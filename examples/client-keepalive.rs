@@ -0,0 +1,36 @@
+//! Enables TCP keepalive on a client connection, so that a peer which disappeared without
+//! closing the connection (e.g. behind a NAT that dropped the mapping) is detected by the OS
+//! even when no WebSocket pings are sent.
+//!
+//! Which keepalive parameters can be set depends on the platform, see
+//! `tungstenite::stream::TcpKeepalive`. Unsupported parameters are ignored.
+//!
+//! To configure a socket that is not opened by `connect`, create the `TcpStream` yourself, set
+//! the options with e.g. socket2 and pass it to `tungstenite::client` or
+//! `tungstenite::client_tls`.
+
+use std::time::Duration;
+
+use tungstenite::{
+    client::connect_with_config, protocol::WebSocketConfig, stream::TcpKeepalive, Message,
+};
+
+fn main() {
+    env_logger::init();
+
+    let keepalive = TcpKeepalive {
+        time: Some(Duration::from_secs(60)),
+        interval: Some(Duration::from_secs(10)),
+        retries: Some(3),
+    };
+    let config = WebSocketConfig::builder().with_tcp_keepalive(Some(keepalive)).build();
+    // Works for TLS connections too, the options are set on the underlying `TcpStream`.
+    let (mut socket, _) =
+        connect_with_config("ws://localhost:3012/socket", Some(config), 3).expect("Can't connect");
+
+    socket.send(Message::Text("Hello WebSocket".into())).unwrap();
+    for msg in socket.incoming() {
+        let msg = msg.expect("Error reading message");
+        println!("Received: {msg}");
+    }
+}
@@ -13,9 +13,8 @@ fn main() {
     }
 
     socket.send(Message::Text("Hello WebSocket".into())).unwrap();
-    loop {
-        let msg = socket.read().expect("Error reading message");
+    for msg in socket.incoming() {
+        let msg = msg.expect("Error reading message");
         println!("Received: {msg}");
     }
-    // socket.close(None);
 }
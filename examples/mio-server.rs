@@ -0,0 +1,113 @@
+//! A non-blocking echo server which drives the WebSocket handshake and the connections
+//! from a single `mio` event loop, without spawning a thread per connection.
+
+use std::{collections::HashMap, io};
+
+use log::*;
+use mio::{
+    net::{TcpListener, TcpStream},
+    Events, Interest, Poll, Token,
+};
+use tungstenite::{
+    handshake::{server::NoCallback, MidHandshake},
+    util::NonBlockingResult,
+    Error, HandshakeError, ServerHandshake, WebSocket,
+};
+
+const SERVER: Token = Token(0);
+
+enum Connection {
+    /// The handshake is still in progress.
+    Handshaking(MidHandshake<ServerHandshake<TcpStream, NoCallback>>),
+    /// The handshake is done, WebSocket messages are being exchanged.
+    Open(WebSocket<TcpStream>),
+}
+
+/// Progress the connection as far as possible without blocking.
+///
+/// Returns `None` once the connection is finished and should be dropped.
+fn drive(connection: Connection) -> Option<Connection> {
+    let mut websocket = match connection {
+        Connection::Handshaking(mid) => match mid.handshake() {
+            Ok(websocket) => websocket,
+            // The stream would block, resume on the next readiness event.
+            Err(HandshakeError::Interrupted(mid)) => return Some(Connection::Handshaking(mid)),
+            Err(HandshakeError::Failure(err)) => {
+                error!("Handshake failed: {err}");
+                return None;
+            }
+        },
+        Connection::Open(websocket) => websocket,
+    };
+
+    // `mio` readiness is edge-triggered, so read until the stream would block.
+    let result = loop {
+        match websocket.read().no_block() {
+            Ok(Some(msg)) if msg.is_text() || msg.is_binary() => {
+                if let Err(err) = websocket.write(msg).no_block() {
+                    break Err(err);
+                }
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => break websocket.flush().no_block().map(|_| ()),
+            Err(err) => break Err(err),
+        }
+    };
+
+    match result {
+        Ok(()) => Some(Connection::Open(websocket)),
+        Err(Error::ConnectionClosed) => None,
+        Err(err) => {
+            error!("Connection error: {err}");
+            None
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    env_logger::init();
+
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(128);
+
+    let mut server = TcpListener::bind("127.0.0.1:3012".parse().unwrap())?;
+    poll.registry().register(&mut server, SERVER, Interest::READABLE)?;
+
+    let mut connections = HashMap::new();
+    let mut next_token = SERVER.0 + 1;
+
+    loop {
+        poll.poll(&mut events, None)?;
+
+        for event in events.iter() {
+            match event.token() {
+                SERVER => loop {
+                    let (mut stream, addr) = match server.accept() {
+                        Ok(accepted) => accepted,
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(err) => return Err(err),
+                    };
+                    info!("Accepted connection from {addr}");
+
+                    let token = Token(next_token);
+                    next_token += 1;
+                    poll.registry().register(
+                        &mut stream,
+                        token,
+                        Interest::READABLE | Interest::WRITABLE,
+                    )?;
+
+                    let mid = ServerHandshake::start(stream, NoCallback, None);
+                    if let Some(connection) = drive(Connection::Handshaking(mid)) {
+                        connections.insert(token, connection);
+                    }
+                },
+                token => {
+                    if let Some(connection) = connections.remove(&token).and_then(drive) {
+                        connections.insert(token, connection);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use std::{env, fs::File, io, net::TcpListener, thread::spawn};
+
+use tungstenite::{accept, Error};
+
+/// A server that saves each message to a file without keeping the message in memory, so
+/// messages may be much larger than the available memory.
+fn main() {
+    env_logger::init();
+    let server = TcpListener::bind("127.0.0.1:3012").unwrap();
+    for (connection, stream) in server.incoming().enumerate() {
+        spawn(move || {
+            let mut websocket = accept(stream.unwrap()).unwrap();
+            for n in 0.. {
+                let mut reader = match websocket.read_stream() {
+                    Ok(reader) => reader,
+                    Err(Error::ConnectionClosed) => break,
+                    Err(err) => panic!("{err}"),
+                };
+                let kind = if reader.is_text() { "text" } else { "binary" };
+                let path = env::temp_dir().join(format!("message-{connection}-{n}"));
+                let mut file = File::create(&path).unwrap();
+                // The payload is written to the file frame by frame as it arrives.
+                let size = io::copy(&mut reader, &mut file).unwrap();
+                println!("Saved a {kind} message of {size} bytes to {}", path.display());
+            }
+        });
+    }
+}
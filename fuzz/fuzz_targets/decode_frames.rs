@@ -0,0 +1,9 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate tungstenite;
+
+use tungstenite::protocol::{frame::decode_frames, WebSocketConfig};
+
+fuzz_target!(|data: &[u8]| {
+    decode_frames(data, &WebSocketConfig::default()).ok();
+});
@@ -6,18 +6,18 @@ use std::{
     result::Result as StdResult,
 };
 
-use http::{request::Parts, HeaderName, Uri};
+use http::{request::Parts, uri::Authority, HeaderName, Uri};
 use log::*;
 
 use crate::{
-    handshake::client::{generate_key, Request, Response},
+    handshake::client::{generate_key, Request, Response, WEBSOCKET_HEADERS},
     protocol::WebSocketConfig,
     stream::MaybeTlsStream,
 };
 
 use crate::{
-    error::{Error, Result, UrlError},
-    handshake::{client::ClientHandshake, HandshakeError},
+    error::{Error, ProtocolError, Result, UrlError},
+    handshake::{client::ClientHandshake, HandshakeError, HandshakeRole},
     protocol::WebSocket,
     stream::{Mode, NoDelay},
 };
@@ -39,45 +39,58 @@ use crate::{
 /// you want to use other TLS libraries, use `client` instead. There is no need to enable any of
 /// the `*-tls` features if you don't call `connect` since it's the only function that uses them.
 ///
+/// `TCP_NODELAY` is set on the connected socket, as well as TCP keepalive if
+/// `WebSocketConfig::tcp_keepalive` is set (requires the `tcp-keepalive` feature). Other socket
+/// options can be set through [`MaybeTlsStream::get_ref`] once connected.
+///
 /// [readme]: https://github.com/snapview/tungstenite-rs/#features
 pub fn connect_with_config<Req: IntoClientRequest>(
     request: Req,
     config: Option<WebSocketConfig>,
     max_redirects: u8,
 ) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)> {
-    fn try_client_handshake(
-        request: Request,
-        config: Option<WebSocketConfig>,
-    ) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)> {
-        let uri = request.uri();
-        let mode = uri_mode(uri)?;
-
-        #[cfg(not(any(feature = "native-tls", feature = "__rustls-tls")))]
-        if let Mode::Tls = mode {
-            return Err(Error::Url(UrlError::TlsFeatureNotEnabled));
-        }
-
-        let host = request.uri().host().ok_or(Error::Url(UrlError::NoHostName))?;
-        let host = if host.starts_with('[') { &host[1..host.len() - 1] } else { host };
-        let port = uri.port_u16().unwrap_or(match mode {
-            Mode::Plain => 80,
-            Mode::Tls => 443,
-        });
-        let addrs = (host, port).to_socket_addrs()?;
-        let mut stream = connect_to_some(addrs.as_slice(), request.uri())?;
-        NoDelay::set_nodelay(&mut stream, true)?;
+    connect_redirecting(request, max_redirects, |request| {
+        let stream = connect_tcp(&request, config)?;
 
         #[cfg(not(any(feature = "native-tls", feature = "__rustls-tls")))]
         let client = client_with_config(request, MaybeTlsStream::Plain(stream), config);
         #[cfg(any(feature = "native-tls", feature = "__rustls-tls"))]
         let client = crate::tls::client_tls_with_config(request, stream, config, None);
 
-        client.map_err(|e| match e {
-            HandshakeError::Failure(f) => f,
-            HandshakeError::Interrupted(_) => panic!("Bug: blocking handshake not blocked"),
-        })
-    }
+        client.map_err(blocking_handshake_error)
+    })
+}
+
+/// Connect to the given WebSocket in blocking mode, using the given connector for TLS.
+///
+/// The same as [`connect_with_config`], but instead of building its own TLS configuration the
+/// supplied [`Connector`](crate::Connector) is used, e.g. a pre-configured
+/// `rustls::ClientConfig` with a custom certificate verifier or a `native_tls::TlsConnector`
+/// with client certificates. The connector is reused when following redirects.
+#[cfg(any(feature = "native-tls", feature = "__rustls-tls"))]
+pub fn connect_with_connector<Req: IntoClientRequest>(
+    request: Req,
+    config: Option<WebSocketConfig>,
+    max_redirects: u8,
+    connector: crate::Connector,
+) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)> {
+    connect_redirecting(request, max_redirects, |request| {
+        let stream = connect_tcp(&request, config)?;
+        crate::tls::client_tls_with_config(request, stream, config, Some(connector.clone()))
+            .map_err(blocking_handshake_error)
+    })
+}
 
+/// Perform `handshake` for the request, retrying with the new location on redirects.
+fn connect_redirecting<Req, F>(
+    request: Req,
+    max_redirects: u8,
+    mut handshake: F,
+) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)>
+where
+    Req: IntoClientRequest,
+    F: FnMut(Request) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)>,
+{
     fn create_request(parts: &Parts, uri: &Uri) -> Request {
         let mut builder =
             Request::builder().uri(uri.clone()).method(parts.method.clone()).version(parts.version);
@@ -91,7 +104,7 @@ pub fn connect_with_config<Req: IntoClientRequest>(
     for attempt in 0..=max_redirects {
         let request = create_request(&parts, &uri);
 
-        match try_client_handshake(request, config) {
+        match handshake(request) {
             Err(Error::Http(res)) if res.status().is_redirection() && attempt < max_redirects => {
                 if let Some(location) = res.headers().get("Location") {
                     uri = location.to_str()?.parse::<Uri>()?;
@@ -109,6 +122,40 @@ pub fn connect_with_config<Req: IntoClientRequest>(
     unreachable!("Bug in a redirect handling logic")
 }
 
+/// Open a TCP connection to the host of the request.
+#[cfg_attr(not(feature = "tcp-keepalive"), allow(unused_variables))]
+fn connect_tcp(request: &Request, config: Option<WebSocketConfig>) -> Result<TcpStream> {
+    let uri = request.uri();
+    let mode = uri_mode(uri)?;
+
+    #[cfg(not(any(feature = "native-tls", feature = "__rustls-tls")))]
+    if let Mode::Tls = mode {
+        return Err(Error::Url(UrlError::TlsFeatureNotEnabled));
+    }
+
+    let host = uri.host().ok_or(Error::Url(UrlError::NoHostName))?;
+    let host = if host.starts_with('[') { &host[1..host.len() - 1] } else { host };
+    let port = uri.port_u16().unwrap_or(match mode {
+        Mode::Plain => 80,
+        Mode::Tls => 443,
+    });
+    let addrs = (host, port).to_socket_addrs()?;
+    let mut stream = connect_to_some(addrs.as_slice(), uri)?;
+    NoDelay::set_nodelay(&mut stream, true)?;
+    #[cfg(feature = "tcp-keepalive")]
+    if let Some(keepalive) = config.and_then(|c| c.tcp_keepalive) {
+        keepalive.apply(&stream)?;
+    }
+    Ok(stream)
+}
+
+fn blocking_handshake_error<Role: HandshakeRole>(err: HandshakeError<Role>) -> Error {
+    match err {
+        HandshakeError::Failure(f) => f,
+        HandshakeError::Interrupted(_) => panic!("Bug: blocking handshake not blocked"),
+    }
+}
+
 /// Connect to the given WebSocket in blocking mode.
 ///
 /// The URL may be either ws:// or wss://.
@@ -277,6 +324,15 @@ impl<'h, 'b> IntoClientRequest for httparse::Request<'h, 'b> {
 /// Builder for a custom [`IntoClientRequest`] with options to add
 /// custom additional headers and sub protocols.
 ///
+/// The connection is always made to the host in the URI, but the `Host` header sent in the
+/// handshake can be overridden with [`with_host`](Self::with_host), e.g. to reach a specific
+/// virtual host through a bastion.
+///
+/// Headers set on the builder are kept when [`connect_with_config`] follows redirects. The
+/// headers required by the handshake itself (`Host`, `Connection`, `Upgrade`,
+/// `Sec-WebSocket-Version` and `Sec-WebSocket-Key`) can not be set with
+/// [`with_header`](Self::with_header).
+///
 /// # Example
 ///
 /// ```rust no_run
@@ -287,6 +343,7 @@ impl<'h, 'b> IntoClientRequest for httparse::Request<'h, 'b> {
 /// let uri: Uri = "ws://localhost:3012/socket".parse().unwrap();
 /// let token = "my_jwt_token";
 /// let builder = ClientRequestBuilder::new(uri)
+///     .with_user_agent("my-client/1.0")
 ///     .with_header("Authorization", format!("Bearer {token}"))
 ///     .with_sub_protocol("my_sub_protocol");
 /// let socket = connect(builder).unwrap();
@@ -294,6 +351,10 @@ impl<'h, 'b> IntoClientRequest for httparse::Request<'h, 'b> {
 #[derive(Debug, Clone)]
 pub struct ClientRequestBuilder {
     uri: Uri,
+    /// Override for the `Host` header
+    host: Option<String>,
+    /// `User-Agent` header
+    user_agent: Option<String>,
     /// Additional [`Request`] handshake headers
     additional_headers: Vec<(String, String)>,
     /// Handsake subprotocols
@@ -304,10 +365,41 @@ impl ClientRequestBuilder {
     /// Initializes an empty request builder
     #[must_use]
     pub const fn new(uri: Uri) -> Self {
-        Self { uri, additional_headers: Vec::new(), subprotocols: Vec::new() }
+        Self {
+            uri,
+            host: None,
+            user_agent: None,
+            additional_headers: Vec::new(),
+            subprotocols: Vec::new(),
+        }
+    }
+
+    /// Sets the `User-Agent` header of the handshake request, replacing any previous value.
+    pub fn with_user_agent<U>(mut self, user_agent: U) -> Self
+    where
+        U: Into<String>,
+    {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Sends `host` (optionally with a port) as the `Host` header instead of the URI's authority.
+    ///
+    /// The connection is still made to the address in the URI.
+    pub fn with_host<H>(mut self, host: H) -> Self
+    where
+        H: Into<String>,
+    {
+        self.host = Some(host.into());
+        self
     }
 
-    /// Adds (`key`, `value`) as an additional header to the handshake request
+    /// Adds (`key`, `value`) as an additional header to the handshake request.
+    ///
+    /// Fails with [`ProtocolError::InvalidHeader`] when converted into a request if `key` is one
+    /// of the headers generated by the handshake: `Host`, `Connection`, `Upgrade`,
+    /// `Sec-WebSocket-Version` or `Sec-WebSocket-Key`. Use [`with_host`](Self::with_host) to send
+    /// a different `Host` header.
     pub fn with_header<K, V>(mut self, key: K, value: V) -> Self
     where
         K: Into<String>,
@@ -331,8 +423,21 @@ impl IntoClientRequest for ClientRequestBuilder {
     fn into_client_request(self) -> Result<Request> {
         let mut request = self.uri.into_client_request()?;
         let headers = request.headers_mut();
+        if let Some(host) = self.host {
+            if host.contains('@') || host.parse::<Authority>().is_err() {
+                return Err(Error::Url(UrlError::InvalidHost(host)));
+            }
+            headers.insert("Host", host.parse()?);
+        }
+        if let Some(user_agent) = self.user_agent {
+            headers.insert("User-Agent", user_agent.parse()?);
+        }
         for (k, v) in self.additional_headers {
             let key = HeaderName::try_from(k)?;
+            // Headers mandated by the handshake are generated, never taken from the user.
+            if WEBSOCKET_HEADERS.iter().any(|h| key.as_str().eq_ignore_ascii_case(h)) {
+                return Err(Error::Protocol(ProtocolError::InvalidHeader(key)));
+            }
             let value = v.parse()?;
             headers.append(key, value);
         }
@@ -343,3 +448,54 @@ impl IntoClientRequest for ClientRequestBuilder {
         Ok(request)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientRequestBuilder, IntoClientRequest};
+    use crate::error::{Error, ProtocolError, UrlError};
+
+    #[test]
+    fn default_headers() {
+        let request = ClientRequestBuilder::new("ws://127.0.0.1/".parse().unwrap())
+            .with_user_agent("first")
+            .with_user_agent("my-client/1.0")
+            .with_header("X-Custom", "value")
+            .into_client_request()
+            .unwrap();
+        assert_eq!(
+            request.headers().get_all("User-Agent").iter().collect::<Vec<_>>(),
+            ["my-client/1.0"]
+        );
+        assert_eq!(request.headers().get("X-Custom").unwrap(), "value");
+
+        for name in ["Upgrade", "sec-websocket-key", "Host"] {
+            let result = ClientRequestBuilder::new("ws://127.0.0.1/".parse().unwrap())
+                .with_header(name, "value")
+                .into_client_request();
+            assert!(matches!(
+                result,
+                Err(Error::Protocol(ProtocolError::InvalidHeader(h))) if h == name
+            ));
+        }
+    }
+
+    #[test]
+    fn host_override() {
+        let request = ClientRequestBuilder::new("ws://127.0.0.1:3012/socket".parse().unwrap())
+            .with_host("example.org:8080")
+            .into_client_request()
+            .unwrap();
+        assert_eq!(request.uri().host(), Some("127.0.0.1"));
+        assert_eq!(
+            request.headers().get_all("Host").iter().collect::<Vec<_>>(),
+            ["example.org:8080"]
+        );
+
+        for host in ["", "bad host", "user@example.org"] {
+            let result = ClientRequestBuilder::new("ws://127.0.0.1/".parse().unwrap())
+                .with_host(host)
+                .into_client_request();
+            assert!(matches!(result, Err(Error::Url(UrlError::InvalidHost(h))) if h == host));
+        }
+    }
+}
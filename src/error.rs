@@ -2,7 +2,10 @@
 
 use std::{io, result, str, string};
 
-use crate::protocol::{frame::coding::Data, Message};
+use crate::protocol::{
+    frame::coding::{CloseCode, Data},
+    Message,
+};
 #[cfg(feature = "handshake")]
 use http::{header::HeaderName, Response};
 use thiserror::Error;
@@ -57,6 +60,10 @@ pub enum Error {
     #[error("Write buffer is full")]
     WriteBufferFull(Message),
     /// UTF coding error.
+    ///
+    /// Returned when a text message received is not valid UTF-8. If
+    /// [`WebSocketConfig::close_on_error`](crate::protocol::WebSocketConfig::close_on_error)
+    /// is enabled, a close frame with code 1007 has been sent to the peer.
     #[error("UTF-8 encoding error")]
     Utf8,
     /// Attack attempt detected.
@@ -147,6 +154,12 @@ pub enum CapacityError {
         /// The maximum allowed message size.
         max_size: usize,
     },
+    /// Message consists of more fragments than allowed.
+    #[error("Message has too many fragments (more than {max_fragments})")]
+    TooManyFragments {
+        /// The maximum allowed number of fragments.
+        max_fragments: usize,
+    },
 }
 
 /// Indicates the specific type/cause of a subprotocol header error.
@@ -185,16 +198,25 @@ pub enum ProtocolError {
     /// Missing `Sec-WebSocket-Version: 13` HTTP header.
     #[error("No \"Sec-WebSocket-Version: 13\" header")]
     MissingSecWebSocketVersionHeader,
+    /// The `Sec-WebSocket-Version` HTTP header specifies a version other than 13.
+    #[error("Unsupported \"Sec-WebSocket-Version\", only version 13 is supported")]
+    UnsupportedSecWebSocketVersion,
     /// Missing `Sec-WebSocket-Key` HTTP header.
     #[error("No \"Sec-WebSocket-Key\" header")]
     MissingSecWebSocketKey,
+    /// The `Sec-WebSocket-Key` header is not a base64-encoded 16-byte value.
+    #[error("Invalid \"Sec-WebSocket-Key\" header")]
+    InvalidSecWebSocketKey,
     /// The `Sec-WebSocket-Accept` header is either not present or does not specify the correct key value.
     #[error("Key mismatch in \"Sec-WebSocket-Accept\" header")]
     SecWebSocketAcceptKeyMismatch,
     /// The `Sec-WebSocket-Protocol` header was invalid
     #[error("SubProtocol error: {0}")]
-    SecWebSocketSubProtocolError(SubProtocolError),
+    SecWebSocketSubProtocolError(#[source] SubProtocolError),
     /// Garbage data encountered after client request.
+    #[deprecated(
+        note = "No longer returned, data sent right after the request is passed to the WebSocket"
+    )]
     #[error("Junk after client request")]
     JunkAfterRequest,
     /// Custom responses must be unsuccessful.
@@ -229,6 +251,9 @@ pub enum ProtocolError {
     /// Control frames must not be fragmented.
     #[error("Fragmented control frame")]
     FragmentedControlFrame,
+    /// The most significant bit of a 64-bit payload length must be 0.
+    #[error("Invalid payload length (most significant bit is set)")]
+    InvalidLength,
     /// Control frames must have a payload of 125 bytes or less.
     #[error("Control frame too big (payload must be 125 bytes or less)")]
     ControlFrameTooBig,
@@ -253,6 +278,9 @@ pub enum ProtocolError {
     /// The payload for the closing frame is invalid.
     #[error("Invalid close sequence")]
     InvalidCloseSequence,
+    /// The close code is not allowed to be sent or received.
+    #[error("Invalid close code: {0}")]
+    InvalidCloseCode(CloseCode),
 }
 
 /// Indicates the specific type/cause of URL error.
@@ -276,6 +304,9 @@ pub enum UrlError {
     /// The URL does not include a path/query.
     #[error("No path/query in URL")]
     NoPathOrQuery,
+    /// The host given to override the `Host` header is not a valid host.
+    #[error("Invalid host: {0}")]
+    InvalidHost(String),
 }
 
 /// TLS errors.
@@ -299,3 +330,39 @@ pub enum TlsError {
     #[error("Invalid DNS name")]
     InvalidDnsName,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error as _;
+
+    /// Format the error and its sources like `anyhow` does with `{:#}`.
+    fn format_chain(err: &dyn std::error::Error) -> String {
+        let mut output = err.to_string();
+        let mut source = err.source();
+        while let Some(err) = source {
+            output.push_str(": ");
+            output.push_str(&err.to_string());
+            source = err.source();
+        }
+        output
+    }
+
+    #[test]
+    fn source_chain() {
+        let err = Error::from(io::Error::new(io::ErrorKind::Other, "broken pipe"));
+        assert_eq!(err.source().unwrap().to_string(), "broken pipe");
+        assert!(format_chain(&err).ends_with(": broken pipe"));
+
+        let err = Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(
+            SubProtocolError::NoSubProtocol,
+        ));
+        assert!(format_chain(&err).ends_with(": Server sent no subprotocol"));
+        let source = err.source().unwrap();
+        assert!(source.is::<ProtocolError>());
+        assert!(source.source().unwrap().is::<SubProtocolError>());
+
+        assert!(Error::ConnectionClosed.source().is_none());
+    }
+}
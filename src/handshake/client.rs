@@ -13,7 +13,7 @@ use log::*;
 
 use super::{
     derive_accept_key,
-    headers::{FromHttparse, MAX_HEADERS},
+    headers::{FromHttparse, Redacted, MAX_HEADERS},
     machine::{HandshakeMachine, StageResult, TryParse},
     HandshakeRole, MidHandshake, ProcessingResult,
 };
@@ -72,7 +72,7 @@ impl<S: Read + Write> ClientHandshake<S> {
         };
 
         trace!("Client handshake initiated.");
-        Ok(MidHandshake { role: client, machine })
+        Ok(MidHandshake { role: client, machine, observer: None })
     }
 }
 
@@ -92,6 +92,11 @@ impl<S: Read + Write> HandshakeRole for ClientHandshake<S> {
                 let result = match self.verify_data.verify_response(result) {
                     Ok(r) => r,
                     Err(Error::Http(mut e)) => {
+                        let mut tail = tail;
+                        if let Some(max) = self.config.and_then(|c| c.max_handshake_error_body_size)
+                        {
+                            tail.truncate(max);
+                        }
                         *e.body_mut() = Some(tail);
                         return Err(Error::Http(e));
                     }
@@ -99,16 +104,31 @@ impl<S: Read + Write> HandshakeRole for ClientHandshake<S> {
                 };
 
                 debug!("Client handshake done.");
-                let websocket =
+                let mut websocket =
                     WebSocket::from_partially_read(stream, tail, Role::Client, self.config);
+                websocket.set_subprotocol(
+                    result
+                        .headers()
+                        .get("Sec-WebSocket-Protocol")
+                        .and_then(|h| h.to_str().ok())
+                        .map(ToString::to_string),
+                );
                 ProcessingResult::Done((websocket, result))
             }
         })
     }
 }
 
+const KEY_HEADERNAME: &str = "Sec-WebSocket-Key";
+
+/// Headers that must be present in a correct request. They are generated for the handshake,
+/// so they can not be set by the user.
+pub(crate) const WEBSOCKET_HEADERS: [&str; 5] =
+    ["Host", "Connection", "Upgrade", "Sec-WebSocket-Version", KEY_HEADERNAME];
+
 /// Verifies and generates a client WebSocket request from the original request and extracts a WebSocket key from it.
 pub fn generate_request(mut request: Request) -> Result<(Vec<u8>, String)> {
+    trace!("Request: {:?}", Redacted::new(&request));
     let mut req = Vec::new();
     write!(
         req,
@@ -118,11 +138,6 @@ pub fn generate_request(mut request: Request) -> Result<(Vec<u8>, String)> {
     )
     .unwrap();
 
-    // Headers that must be present in a correct request.
-    const KEY_HEADERNAME: &str = "Sec-WebSocket-Key";
-    const WEBSOCKET_HEADERS: [&str; 5] =
-        ["Host", "Connection", "Upgrade", "Sec-WebSocket-Version", KEY_HEADERNAME];
-
     // We must extract a WebSocket key from a properly formed request or fail if it's not present.
     let key = request
         .headers()
@@ -180,7 +195,6 @@ pub fn generate_request(mut request: Request) -> Result<(Vec<u8>, String)> {
     }
 
     writeln!(req, "\r").unwrap();
-    trace!("Request: {:?}", String::from_utf8_lossy(&req));
     Ok((req, key))
 }
 
@@ -321,8 +335,95 @@ pub fn generate_key() -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::{super::machine::TryParse, generate_key, generate_request, Response};
-    use crate::client::IntoClientRequest;
+    use super::{
+        super::{machine::TryParse, HandshakeError},
+        generate_key, generate_request, ClientHandshake, Response, VerifyData,
+    };
+    use crate::{
+        client::IntoClientRequest,
+        error::{Error, ProtocolError},
+        protocol::{HandshakeData, WebSocketConfig},
+        stream::ReadWrite,
+    };
+
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn handshake_data() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let stream = ReadWrite::new(Cursor::new(b"HTTP/1.1 400 Bad Request\r\n\r\n"), Vec::new());
+        let request = "ws://localhost/getCaseCount".into_client_request().unwrap();
+        let mut mid = ClientHandshake::start(stream, request, None).unwrap();
+        let recorded = Arc::clone(&sent);
+        mid.set_on_handshake_data(move |data| {
+            if let HandshakeData::Sent(data) = data {
+                recorded.lock().unwrap().extend_from_slice(data);
+            }
+        });
+        assert!(mid.handshake().is_err());
+
+        let sent = String::from_utf8(sent.lock().unwrap().clone()).unwrap();
+        assert!(sent.starts_with("GET /getCaseCount HTTP/1.1\r\n"));
+        assert!(sent.contains("\r\nSec-WebSocket-Key: "));
+    }
+
+    #[test]
+    fn closed_after_handshake() {
+        const RESPONSE: &[u8] = b"\
+            HTTP/1.1 101 Switching Protocols\r\n\
+            Connection: Upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+            \r\n";
+        // The server hangs up right after the response or in the middle of a frame.
+        for tail in [&[][..], &[0x81, 0x05, b'H']] {
+            let stream = ReadWrite::new(Cursor::new([RESPONSE, tail].concat()), Vec::new());
+            let mut request = "ws://localhost/".into_client_request().unwrap();
+            request
+                .headers_mut()
+                .insert("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==".parse().unwrap());
+            let (mut socket, _) =
+                ClientHandshake::start(stream, request, None).unwrap().handshake().unwrap();
+            assert!(matches!(
+                socket.read(),
+                Err(Error::Protocol(ProtocolError::ResetWithoutClosingHandshake))
+            ));
+            assert!(matches!(socket.read(), Err(Error::AlreadyClosed)));
+        }
+    }
+
+    #[test]
+    fn error_body_limit() {
+        const RESPONSE: &[u8] = b"HTTP/1.1 403 Forbidden\r\nContent-Length: 1000\r\n\r\n";
+        let body = vec![b'x'; 1000];
+        let handshake = |config| {
+            let stream = ReadWrite::new(Cursor::new([RESPONSE, &body].concat()), Vec::new());
+            let request = "ws://localhost/".into_client_request().unwrap();
+            ClientHandshake::start(stream, request, config).unwrap().handshake()
+        };
+
+        let config = WebSocketConfig {
+            max_handshake_error_body_size: Some(16),
+            ..WebSocketConfig::default()
+        };
+        match handshake(Some(config)) {
+            Err(HandshakeError::Failure(Error::Http(response))) => {
+                assert_eq!(response.status(), 403);
+                assert_eq!(response.body().as_deref(), Some(&body[..16]));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        match handshake(None) {
+            Err(HandshakeError::Failure(Error::Http(response))) => {
+                assert_eq!(response.body().as_deref(), Some(&body[..]));
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
 
     #[test]
     fn random_keys() {
@@ -386,6 +487,31 @@ mod tests {
         assert_eq!(resp.headers().get("Content-Type").unwrap(), &b"text/html"[..],);
     }
 
+    #[test]
+    fn accept_key_verification() {
+        let verify =
+            VerifyData { accept_key: "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".into(), subprotocols: None };
+        let response = |accept: &str| {
+            let data = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept:{accept}\r\n\
+                 \r\n"
+            );
+            Response::try_parse(data.as_bytes()).unwrap().unwrap().1
+        };
+
+        // Surrounding whitespace is not part of the value.
+        assert!(verify.verify_response(response(" s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")).is_ok());
+        assert!(verify.verify_response(response("\ts3pPLMBiTxaQ9kYGzzhZRbK+xOo=  ")).is_ok());
+        // Base64 is case sensitive.
+        assert!(matches!(
+            verify.verify_response(response(" S3PPLMBITXAQ9KYGZZHZRBK+XOO=")),
+            Err(Error::Protocol(ProtocolError::SecWebSocketAcceptKeyMismatch))
+        ));
+    }
+
     #[test]
     fn invalid_custom_request() {
         let request = http::Request::builder().method("GET").body(()).unwrap();
@@ -1,6 +1,11 @@
 //! HTTP Request and response header handling.
 
-use http::header::{HeaderMap, HeaderName, HeaderValue};
+use std::fmt;
+
+use http::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Request, Response,
+};
 use httparse::Status;
 
 use super::machine::TryParse;
@@ -9,6 +14,85 @@ use crate::error::Result;
 /// Limit for the number of header lines.
 pub const MAX_HEADERS: usize = 124;
 
+/// Headers whose values are masked by [`Redacted`] by default.
+pub const SENSITIVE_HEADERS: &[&str] = &[
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "Set-Cookie",
+    "Sec-WebSocket-Key",
+    "Sec-WebSocket-Accept",
+];
+
+/// Wrapper around a [`HeaderMap`], [`Request`] or [`Response`] whose `Debug` output masks the
+/// values of sensitive headers, so that handshake data can be logged without leaking credentials.
+///
+/// ```
+/// use tungstenite::{client::IntoClientRequest, handshake::headers::Redacted};
+///
+/// let mut request = "ws://localhost/".into_client_request().unwrap();
+/// request.headers_mut().insert("Authorization", "Bearer secret".parse().unwrap());
+/// let output = format!("{:?}", Redacted::new(&request));
+/// assert!(!output.contains("secret"));
+/// ```
+pub struct Redacted<'a, T> {
+    inner: &'a T,
+    sensitive: &'a [&'a str],
+}
+
+impl<'a, T> Redacted<'a, T> {
+    /// Wrap `inner`, masking the [`SENSITIVE_HEADERS`].
+    pub fn new(inner: &'a T) -> Self {
+        Self::with_headers(inner, SENSITIVE_HEADERS)
+    }
+
+    /// Wrap `inner`, masking the given (case-insensitive) header names instead of the defaults.
+    pub fn with_headers(inner: &'a T, sensitive: &'a [&'a str]) -> Self {
+        Redacted { inner, sensitive }
+    }
+
+    fn wrap<U>(&self, inner: &'a U) -> Redacted<'a, U> {
+        Redacted::with_headers(inner, self.sensitive)
+    }
+}
+
+impl fmt::Debug for Redacted<'_, HeaderMap> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (name, value) in self.inner {
+            if self.sensitive.iter().any(|s| name.as_str().eq_ignore_ascii_case(s)) {
+                map.entry(name, &"<redacted>");
+            } else {
+                map.entry(name, value);
+            }
+        }
+        map.finish()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Redacted<'_, Request<T>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("method", self.inner.method())
+            .field("uri", self.inner.uri())
+            .field("version", &self.inner.version())
+            .field("headers", &self.wrap(self.inner.headers()))
+            .field("body", self.inner.body())
+            .finish()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Redacted<'_, Response<T>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.inner.status())
+            .field("version", &self.inner.version())
+            .field("headers", &self.wrap(self.inner.headers()))
+            .field("body", self.inner.body())
+            .finish()
+    }
+}
+
 /// Trait to convert raw objects into HTTP parseables.
 pub(crate) trait FromHttparse<T>: Sized {
     /// Convert raw object into parsed HTTP headers.
@@ -41,7 +125,7 @@ impl TryParse for HeaderMap {
 #[cfg(test)]
 mod tests {
 
-    use super::{super::machine::TryParse, HeaderMap};
+    use super::{super::machine::TryParse, HeaderMap, Redacted};
 
     #[test]
     fn headers() {
@@ -70,6 +154,26 @@ mod tests {
         assert_eq!(iter.next(), None);
     }
 
+    #[test]
+    fn headers_redacted() {
+        const DATA: &[u8] = b"Host: foo.com\r\n\
+              authorization: Basic c2VjcmV0\r\n\
+              Cookie: session=secret\r\n\
+              X-Custom: visible\r\n\
+              \r\n";
+        let (_, hdr) = HeaderMap::try_parse(DATA).unwrap().unwrap();
+
+        let output = format!("{:?}", Redacted::new(&hdr));
+        assert!(!output.contains("c2VjcmV0"));
+        assert!(!output.contains("session=secret"));
+        assert!(output.contains("\"authorization\": \"<redacted>\""));
+        assert!(output.contains("visible"));
+
+        let output = format!("{:?}", Redacted::with_headers(&hdr, &["x-custom"]));
+        assert!(output.contains("c2VjcmV0"));
+        assert!(!output.contains("visible"));
+    }
+
     #[test]
     fn headers_incomplete() {
         const DATA: &[u8] = b"Host: foo.com\r\n\
@@ -4,8 +4,10 @@ use bytes::Buf;
 use log::*;
 use std::io::{Cursor, Read, Write};
 
+use super::OnHandshakeData;
 use crate::{
     error::{Error, ProtocolError, Result},
+    protocol::HandshakeData,
     util::NonBlockingResult,
     ReadBuffer,
 };
@@ -38,7 +40,16 @@ impl<Stream> HandshakeMachine<Stream> {
 
 impl<Stream: Read + Write> HandshakeMachine<Stream> {
     /// Perform a single handshake round.
-    pub fn single_round<Obj: TryParse>(mut self) -> Result<RoundResult<Obj, Stream>> {
+    pub fn single_round<Obj: TryParse>(self) -> Result<RoundResult<Obj, Stream>> {
+        self.observed_round(None)
+    }
+
+    /// Perform a single handshake round, reporting the raw data read or written by a finished
+    /// stage to `observer`.
+    pub(crate) fn observed_round<Obj: TryParse>(
+        mut self,
+        observer: Option<&mut OnHandshakeData>,
+    ) -> Result<RoundResult<Obj, Stream>> {
         trace!("Doing handshake round.");
         match self.state {
             HandshakeState::Reading(mut buf, mut attack_check) => {
@@ -51,6 +62,9 @@ impl<Stream: Read + Write> HandshakeMachine<Stream> {
                         // The parser has to be reworked in order to work on streams instead
                         // of buffers.
                         Ok(if let Some((size, obj)) = Obj::try_parse(Buf::chunk(&buf))? {
+                            if let Some(observer) = observer {
+                                observer(HandshakeData::Received(&Buf::chunk(&buf)[..size]));
+                            }
                             buf.advance(size);
                             RoundResult::StageFinished(StageResult::DoneReading {
                                 result: obj,
@@ -81,6 +95,9 @@ impl<Stream: Read + Write> HandshakeMachine<Stream> {
                             ..self
                         })
                     } else {
+                        if let Some(observer) = observer {
+                            observer(HandshakeData::Sent(buf.get_ref()));
+                        }
                         RoundResult::Incomplete(HandshakeMachine {
                             state: HandshakeState::Flushing,
                             ..self
@@ -1,4 +1,37 @@
 //! WebSocket handshake control.
+//!
+//! The handshake is implemented as a state machine that never blocks by itself. When the
+//! underlying stream returns [`WouldBlock`](std::io::ErrorKind::WouldBlock), the handshake is
+//! suspended and returned as [`HandshakeError::Interrupted`] carrying a [`MidHandshake`]. Once
+//! the stream is ready again, call [`MidHandshake::handshake`] to resume from where it stopped.
+//! This allows driving many handshakes from a single custom event loop (see the `mio-server`
+//! example) without spawning a thread per connection.
+//!
+//! ```no_run
+//! use std::net::{TcpListener, TcpStream};
+//! use tungstenite::{
+//!     handshake::{server::NoCallback, MidHandshake},
+//!     HandshakeError, ServerHandshake,
+//! };
+//!
+//! # fn wait_until_ready(_: &TcpStream) {}
+//! let listener = TcpListener::bind("127.0.0.1:3012").unwrap();
+//! let (stream, _) = listener.accept().unwrap();
+//! stream.set_nonblocking(true).unwrap();
+//!
+//! let mut mid: MidHandshake<ServerHandshake<_, NoCallback>> =
+//!     ServerHandshake::start(stream, NoCallback, None);
+//! let websocket = loop {
+//!     match mid.handshake() {
+//!         Ok(websocket) => break websocket,
+//!         Err(HandshakeError::Interrupted(m)) => {
+//!             wait_until_ready(m.get_ref().get_ref());
+//!             mid = m;
+//!         }
+//!         Err(HandshakeError::Failure(err)) => panic!("handshake failed: {err}"),
+//!     }
+//! };
+//! ```
 
 pub mod client;
 pub mod headers;
@@ -14,13 +47,24 @@ use std::{
 use sha1::{Digest, Sha1};
 
 use self::machine::{HandshakeMachine, RoundResult, StageResult, TryParse};
-use crate::error::Error;
+use crate::{
+    error::Error,
+    protocol::{HandshakeData, Hook},
+};
+
+/// See [`MidHandshake::set_on_handshake_data`].
+type OnHandshakeData = dyn FnMut(HandshakeData<'_>) + Send + Sync;
 
-/// A WebSocket handshake.
+/// A WebSocket handshake which is in progress.
+///
+/// This is returned inside [`HandshakeError::Interrupted`] whenever the underlying stream would
+/// block. It holds all the state needed to continue, so no data that has already been read or
+/// written is lost. Call [`handshake`](Self::handshake) again once the stream is ready.
 #[derive(Debug)]
 pub struct MidHandshake<Role: HandshakeRole> {
     role: Role,
     machine: HandshakeMachine<Role::InternalStream>,
+    observer: Option<Hook<OnHandshakeData>>,
 }
 
 impl<Role: HandshakeRole> MidHandshake<Role> {
@@ -34,11 +78,30 @@ impl<Role: HandshakeRole> MidHandshake<Role> {
         &mut self.machine
     }
 
+    /// Set a function called with the raw bytes of the HTTP handshake, exactly as they are sent
+    /// and received, e.g. to debug interoperability issues. It replaces the previously set one.
+    ///
+    /// The function is owned by this handshake, so it may keep per-connection state. To set it
+    /// before anything is sent or received, start the handshake with
+    /// [`ClientHandshake::start`](client::ClientHandshake::start) or
+    /// [`ServerHandshake::start`](server::ServerHandshake::start).
+    pub fn set_on_handshake_data(
+        &mut self,
+        observer: impl FnMut(HandshakeData<'_>) + Send + Sync + 'static,
+    ) {
+        self.observer = Some(Hook(Box::new(observer)));
+    }
+
     /// Restarts the handshake process.
+    ///
+    /// Drives the handshake as far as possible. Returns the final result once the handshake is
+    /// complete, [`HandshakeError::Interrupted`] with the updated state if the stream would
+    /// block, or [`HandshakeError::Failure`] if the handshake failed.
     pub fn handshake(mut self) -> Result<Role::FinalResult, HandshakeError<Role>> {
         let mut mach = self.machine;
         loop {
-            mach = match mach.single_round()? {
+            let observer = self.observer.as_mut().map(|Hook(observer)| &mut **observer);
+            mach = match mach.observed_round(observer)? {
                 RoundResult::WouldBlock(m) => {
                     return Err(HandshakeError::Interrupted(MidHandshake { machine: m, ..self }))
                 }
@@ -53,6 +116,9 @@ impl<Role: HandshakeRole> MidHandshake<Role> {
 }
 
 /// A handshake result.
+///
+/// Note that `Interrupted` is not a real failure: it only indicates that the stream returned
+/// [`WouldBlock`](std::io::ErrorKind::WouldBlock) and the handshake should be resumed later.
 pub enum HandshakeError<Role: HandshakeRole> {
     /// Handshake was interrupted (would block).
     Interrupted(MidHandshake<Role>),
@@ -113,6 +179,9 @@ pub enum ProcessingResult<Stream, FinalResult> {
 ///
 /// This function can be used to perform a handshake before passing a raw TCP stream to
 /// [`WebSocket::from_raw_socket`][crate::protocol::WebSocket::from_raw_socket].
+///
+/// The key is used as is, so surrounding whitespace must be stripped beforehand (header values
+/// parsed by this crate already are). The result is base64 and must be compared case-sensitively.
 pub fn derive_accept_key(request_key: &[u8]) -> String {
     // ... field is constructed by concatenating /key/ ...
     // ... with the string "258EAFA5-E914-47DA-95CA-C5AB0DC85B11" (RFC 6455)
@@ -123,13 +192,29 @@ pub fn derive_accept_key(request_key: &[u8]) -> String {
     data_encoding::BASE64.encode(&sha1.finalize())
 }
 
+/// Check whether `key` is a valid `Sec-WebSocket-Key` header value, i.e. a base64-encoded
+/// 16-byte value.
+pub fn is_valid_sec_websocket_key(key: &str) -> bool {
+    data_encoding::BASE64.decode(key.as_bytes()).map_or(false, |key| key.len() == 16)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::derive_accept_key;
+    use super::{derive_accept_key, is_valid_sec_websocket_key};
 
     #[test]
     fn key_conversion() {
         // example from RFC 6455
         assert_eq!(derive_accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
     }
+
+    #[test]
+    fn key_validation() {
+        assert!(is_valid_sec_websocket_key("dGhlIHNhbXBsZSBub25jZQ=="));
+        // 15 and 17 bytes.
+        assert!(!is_valid_sec_websocket_key("dGhlIHNhbXBsZSBub25j"));
+        assert!(!is_valid_sec_websocket_key("dGhlIHNhbXBsZSBub25jZQ4="));
+        assert!(!is_valid_sec_websocket_key("the sample nonce"));
+        assert!(!is_valid_sec_websocket_key(""));
+    }
 }
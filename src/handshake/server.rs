@@ -7,7 +7,8 @@ use std::{
 };
 
 use http::{
-    response::Builder, HeaderMap, Request as HttpRequest, Response as HttpResponse, StatusCode,
+    header, response::Builder, HeaderMap, Request as HttpRequest, Response as HttpResponse,
+    StatusCode,
 };
 use httparse::Status;
 use log::*;
@@ -15,6 +16,7 @@ use log::*;
 use super::{
     derive_accept_key,
     headers::{FromHttparse, MAX_HEADERS},
+    is_valid_sec_websocket_key,
     machine::{HandshakeMachine, StageResult, TryParse},
     HandshakeRole, MidHandshake, ProcessingResult,
 };
@@ -32,6 +34,15 @@ pub type Response = HttpResponse<()>;
 /// Server error response type.
 pub type ErrorResponse = HttpResponse<Option<String>>;
 
+/// Bytes written right after the `101 Switching Protocols` response.
+///
+/// Insert this into the extensions of the response returned by the [`Callback`] to send data
+/// along with the response, e.g. the first frames of the connection, without waiting for another
+/// round trip. The bytes are written as is, so they must be valid unmasked WebSocket frames,
+/// for example created with [`Frame::format`](crate::protocol::frame::Frame::format).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrailingData(pub Vec<u8>);
+
 fn create_parts<T>(request: &HttpRequest<T>) -> Result<Builder> {
     if request.method() != http::Method::GET {
         return Err(Error::Protocol(ProtocolError::WrongHttpMethod));
@@ -43,10 +54,11 @@ fn create_parts<T>(request: &HttpRequest<T>) -> Result<Builder> {
 
     if !request
         .headers()
-        .get("Connection")
-        .and_then(|h| h.to_str().ok())
-        .map(|h| h.split([' ', ',']).any(|p| p.eq_ignore_ascii_case("Upgrade")))
-        .unwrap_or(false)
+        .get_all("Connection")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split([' ', ',']))
+        .any(|p| p.eq_ignore_ascii_case("Upgrade"))
     {
         return Err(Error::Protocol(ProtocolError::MissingConnectionUpgradeHeader));
     }
@@ -61,14 +73,29 @@ fn create_parts<T>(request: &HttpRequest<T>) -> Result<Builder> {
         return Err(Error::Protocol(ProtocolError::MissingUpgradeWebSocketHeader));
     }
 
-    if !request.headers().get("Sec-WebSocket-Version").map(|h| h == "13").unwrap_or(false) {
-        return Err(Error::Protocol(ProtocolError::MissingSecWebSocketVersionHeader));
+    // These headers must not be repeated, so rather than picking one of the values reject the
+    // request altogether.
+    for name in [header::SEC_WEBSOCKET_VERSION, header::SEC_WEBSOCKET_KEY] {
+        if request.headers().get_all(&name).iter().nth(1).is_some() {
+            return Err(Error::Protocol(ProtocolError::InvalidHeader(name)));
+        }
+    }
+
+    match request.headers().get("Sec-WebSocket-Version") {
+        None => return Err(Error::Protocol(ProtocolError::MissingSecWebSocketVersionHeader)),
+        Some(version) if version != "13" => {
+            return Err(Error::Protocol(ProtocolError::UnsupportedSecWebSocketVersion))
+        }
+        Some(_) => {}
     }
 
     let key = request
         .headers()
         .get("Sec-WebSocket-Key")
         .ok_or(Error::Protocol(ProtocolError::MissingSecWebSocketKey))?;
+    if !key.to_str().map_or(false, is_valid_sec_websocket_key) {
+        return Err(Error::Protocol(ProtocolError::InvalidSecWebSocketKey));
+    }
 
     let builder = Response::builder()
         .status(StatusCode::SWITCHING_PROTOCOLS)
@@ -80,6 +107,37 @@ fn create_parts<T>(request: &HttpRequest<T>) -> Result<Builder> {
     Ok(builder)
 }
 
+/// Create the error response to send to the client when `create_response` failed with `err`.
+///
+/// Returns `None` if the client should not be answered at all.
+fn create_error_response(err: &Error) -> Option<ErrorResponse> {
+    match err {
+        // If this version does not match a version understood by the server, the server MUST
+        // abort the WebSocket handshake described in this section and instead send an
+        // appropriate HTTP error code (such as 426 Upgrade Required) and a
+        // |Sec-WebSocket-Version| header field indicating the version(s) the server is capable
+        // of understanding. (RFC 6455)
+        Error::Protocol(ProtocolError::UnsupportedSecWebSocketVersion) => Response::builder()
+            .status(StatusCode::UPGRADE_REQUIRED)
+            .header("Sec-WebSocket-Version", "13")
+            .body(None)
+            .ok(),
+        // If the server, while reading the handshake, finds that the client did not send a
+        // handshake that matches the description below [...], the server MUST stop processing
+        // the client's handshake and return an HTTP response with an appropriate error code
+        // (such as 400 Bad Request). (RFC 6455)
+        Error::Protocol(
+            ProtocolError::MissingConnectionUpgradeHeader
+            | ProtocolError::MissingUpgradeWebSocketHeader
+            | ProtocolError::MissingSecWebSocketVersionHeader
+            | ProtocolError::MissingSecWebSocketKey
+            | ProtocolError::InvalidSecWebSocketKey
+            | ProtocolError::InvalidHeader(_),
+        ) => Response::builder().status(StatusCode::BAD_REQUEST).body(None).ok(),
+        _ => None,
+    }
+}
+
 /// Create a response for the request.
 pub fn create_response(request: &Request) -> Result<Response> {
     Ok(create_parts(request)?.body(())?)
@@ -93,6 +151,23 @@ pub fn create_response_with_body<T1, T2>(
     Ok(create_parts(request)?.body(generate_body())?)
 }
 
+/// Get the subprotocols offered by the client in the `Sec-WebSocket-Protocol` header, in the
+/// client's order of preference.
+///
+/// Both multiple header lines and comma-separated values are handled. Empty entries and
+/// header values that are not valid UTF-8 are skipped.
+pub fn offered_subprotocols<T>(request: &HttpRequest<T>) -> Vec<&str> {
+    request
+        .headers()
+        .get_all("Sec-WebSocket-Protocol")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
 /// Write `response` to the stream `w`.
 pub fn write_response<T>(mut w: impl io::Write, response: &HttpResponse<T>) -> Result<()> {
     writeln!(
@@ -152,6 +227,9 @@ impl<'h, 'b: 'h> FromHttparse<httparse::Request<'h, 'b>> for Request {
 /// handshake request from the client. Specifying a callback allows you to analyze incoming headers
 /// and add additional headers to the response that server sends to the client and/or reject the
 /// connection based on the incoming headers.
+///
+/// Use [`offered_subprotocols`] to get the subprotocols requested by the client and
+/// [`TrailingData`] to send data right after the response.
 pub trait Callback: Sized {
     /// Called whenever the server read the request from the client and is ready to reply to it.
     /// May return additional reply headers.
@@ -201,7 +279,11 @@ pub struct ServerHandshake<S, C> {
     /// WebSocket configuration.
     config: Option<WebSocketConfig>,
     /// Error code/flag. If set, an error will be returned after sending response to the client.
-    error_response: Option<ErrorResponse>,
+    error: Option<Error>,
+    /// Data the client sent right after the request, passed on to the WebSocket.
+    tail: Vec<u8>,
+    /// Subprotocol selected by the callback.
+    subprotocol: Option<String>,
     /// Internal stream type.
     _marker: PhantomData<S>,
 }
@@ -215,10 +297,13 @@ impl<S: Read + Write, C: Callback> ServerHandshake<S, C> {
         trace!("Server handshake initiated.");
         MidHandshake {
             machine: HandshakeMachine::start_read(stream),
+            observer: None,
             role: ServerHandshake {
                 callback: Some(callback),
                 config,
-                error_response: None,
+                error: None,
+                tail: Vec::new(),
+                subprotocol: None,
                 _marker: PhantomData,
             },
         }
@@ -236,11 +321,23 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
     ) -> Result<ProcessingResult<Self::InternalStream, Self::FinalResult>> {
         Ok(match finish {
             StageResult::DoneReading { stream, result, tail } => {
-                if !tail.is_empty() {
-                    return Err(Error::Protocol(ProtocolError::JunkAfterRequest));
-                }
-
-                let response = create_response(&result)?;
+                // A client may pipeline its first frames right after the request.
+                self.tail = tail;
+
+                let response = match create_response(&result) {
+                    Ok(response) => response,
+                    Err(err) => match create_error_response(&err) {
+                        Some(resp) => {
+                            let mut output = vec![];
+                            write_response(&mut output, &resp)?;
+                            self.error = Some(err);
+                            return Ok(ProcessingResult::Continue(HandshakeMachine::start_write(
+                                stream, output,
+                            )));
+                        }
+                        None => return Err(err),
+                    },
+                };
                 let callback_result = if let Some(callback) = self.callback.take() {
                     callback.on_request(&result, response)
                 } else {
@@ -249,8 +346,16 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
 
                 match callback_result {
                     Ok(response) => {
+                        self.subprotocol = response
+                            .headers()
+                            .get("Sec-WebSocket-Protocol")
+                            .and_then(|h| h.to_str().ok())
+                            .map(ToString::to_string);
                         let mut output = vec![];
                         write_response(&mut output, &response)?;
+                        if let Some(TrailingData(data)) = response.extensions().get() {
+                            output.extend_from_slice(data);
+                        }
                         ProcessingResult::Continue(HandshakeMachine::start_write(stream, output))
                     }
 
@@ -259,31 +364,32 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
                             return Err(Error::Protocol(ProtocolError::CustomResponseSuccessful));
                         }
 
-                        self.error_response = Some(resp);
-                        let resp = self.error_response.as_ref().unwrap();
-
                         let mut output = vec![];
-                        write_response(&mut output, resp)?;
+                        write_response(&mut output, &resp)?;
 
                         if let Some(body) = resp.body() {
                             output.extend_from_slice(body.as_bytes());
                         }
 
+                        let (parts, body) = resp.into_parts();
+                        let body = body.map(|b| b.as_bytes().to_vec());
+                        self.error = Some(Error::Http(http::Response::from_parts(parts, body)));
+
                         ProcessingResult::Continue(HandshakeMachine::start_write(stream, output))
                     }
                 }
             }
 
             StageResult::DoneWriting(stream) => {
-                if let Some(err) = self.error_response.take() {
+                if let Some(err) = self.error.take() {
                     debug!("Server handshake failed.");
-
-                    let (parts, body) = err.into_parts();
-                    let body = body.map(|b| b.as_bytes().to_vec());
-                    return Err(Error::Http(http::Response::from_parts(parts, body)));
+                    return Err(err);
                 } else {
                     debug!("Server handshake done.");
-                    let websocket = WebSocket::from_raw_socket(stream, Role::Server, self.config);
+                    let tail = std::mem::take(&mut self.tail);
+                    let mut websocket =
+                        WebSocket::from_partially_read(stream, tail, Role::Server, self.config);
+                    websocket.set_subprotocol(self.subprotocol.take());
                     ProcessingResult::Done(websocket)
                 }
             }
@@ -293,7 +399,48 @@ impl<S: Read + Write, C: Callback> HandshakeRole for ServerHandshake<S, C> {
 
 #[cfg(test)]
 mod tests {
-    use super::{super::machine::TryParse, create_response, Request};
+    use std::{
+        io::{self, Cursor, Read, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use super::{
+        super::{client::Response, machine::TryParse, HandshakeError},
+        create_response, offered_subprotocols, NoCallback, Request, ServerHandshake, TrailingData,
+    };
+    use crate::{
+        error::{Error, ProtocolError},
+        protocol::HandshakeData,
+        Message,
+    };
+
+    /// A stream which reads from a fixed input and records everything written to it.
+    #[derive(Debug)]
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: &[u8]) -> Self {
+            MockStream { input: Cursor::new(input.to_vec()), output: Vec::new() }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 
     #[test]
     fn request_parsing() {
@@ -321,4 +468,216 @@ mod tests {
             b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".as_ref()
         );
     }
+
+    #[test]
+    fn subprotocols() {
+        const DATA: &[u8] = b"\
+            GET /script.ws HTTP/1.1\r\n\
+            Host: foo.com\r\n\
+            Sec-WebSocket-Protocol: chat, superchat\r\n\
+            Sec-WebSocket-Protocol: ,mqtt ,\r\n\
+            \r\n";
+        let (_, req) = Request::try_parse(DATA).unwrap().unwrap();
+        assert_eq!(offered_subprotocols(&req), vec!["chat", "superchat", "mqtt"]);
+
+        let (_, req) = Request::try_parse(b"GET / HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert!(offered_subprotocols(&req).is_empty());
+    }
+
+    #[test]
+    fn request_key_whitespace() {
+        const DATA: &[u8] = b"\
+            GET /script.ws HTTP/1.1\r\n\
+            Host: foo.com\r\n\
+            Connection: upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: \t dGhlIHNhbXBsZSBub25jZQ== \t\r\n\
+            \r\n";
+        let (_, req) = Request::try_parse(DATA).unwrap().unwrap();
+        let response = create_response(&req).unwrap();
+        assert_eq!(
+            response.headers().get("Sec-WebSocket-Accept").unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn pipelined_frame() {
+        const DATA: &[u8] = b"\
+            GET /script.ws HTTP/1.1\r\n\
+            Host: foo.com\r\n\
+            Connection: upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            \r\n\
+            \x81\x82\x00\x00\x00\x00Hi";
+        let mut stream = MockStream::new(DATA);
+        let mut websocket =
+            ServerHandshake::start(&mut stream, NoCallback, None).handshake().unwrap();
+        assert_eq!(websocket.read().unwrap(), Message::Text("Hi".into()));
+    }
+
+    #[test]
+    fn trailing_data() {
+        const DATA: &[u8] = b"\
+            GET /script.ws HTTP/1.1\r\n\
+            Host: foo.com\r\n\
+            Connection: upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            \r\n";
+        let mut stream = MockStream::new(DATA);
+        let callback = |_: &Request, mut response: super::Response| {
+            response.extensions_mut().insert(TrailingData(b"\x81\x05Hello".to_vec()));
+            Ok(response)
+        };
+        ServerHandshake::start(&mut stream, callback, None).handshake().unwrap();
+        assert!(stream.output.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(stream.output.ends_with(b"\r\n\r\n\x81\x05Hello"));
+    }
+
+    #[test]
+    fn selected_subprotocol() {
+        const DATA: &[u8] = b"\
+            GET /script.ws HTTP/1.1\r\n\
+            Host: foo.com\r\n\
+            Connection: upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Protocol: chat, superchat\r\n\
+            \r\n";
+        let callback = |_: &Request, mut response: super::Response| {
+            response.headers_mut().insert("Sec-WebSocket-Protocol", "superchat".parse().unwrap());
+            Ok(response)
+        };
+        let mut stream = MockStream::new(DATA);
+        let websocket = ServerHandshake::start(&mut stream, callback, None).handshake().unwrap();
+        assert_eq!(websocket.subprotocol(), Some("superchat"));
+
+        let mut stream = MockStream::new(DATA);
+        let websocket = ServerHandshake::start(&mut stream, NoCallback, None).handshake().unwrap();
+        assert_eq!(websocket.subprotocol(), None);
+    }
+
+    #[test]
+    fn handshake_data() {
+        const REQUEST: &[u8] = b"\
+            GET /script.ws HTTP/1.1\r\n\
+            Host: foo.com\r\n\
+            Connection: upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            \r\n";
+        let mut input = REQUEST.to_vec();
+        input.extend([0x81, 0x80, 0, 0, 0, 0]);
+        let data = Arc::new(Mutex::new(Vec::new()));
+        let mut stream = MockStream::new(&input);
+        let mut mid = ServerHandshake::start(&mut stream, NoCallback, None);
+        let recorded = Arc::clone(&data);
+        mid.set_on_handshake_data(move |data| {
+            recorded.lock().unwrap().push(match data {
+                HandshakeData::Sent(d) => format!("sent {}", String::from_utf8_lossy(d)),
+                HandshakeData::Received(d) => format!("received {}", String::from_utf8_lossy(d)),
+            });
+        });
+        mid.handshake().unwrap();
+
+        let data = data.lock().unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0], format!("received {}", String::from_utf8_lossy(REQUEST)));
+        assert!(data[0].contains("\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n"));
+        assert_eq!(data[1], format!("sent {}", String::from_utf8_lossy(&stream.output)));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        const DATA: &[u8] = b"\
+            GET /script.ws HTTP/1.1\r\n\
+            Host: foo.com\r\n\
+            Connection: upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 8\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            \r\n";
+        let mut stream = MockStream::new(DATA);
+
+        let err = ServerHandshake::start(&mut stream, NoCallback, None).handshake().unwrap_err();
+        assert!(matches!(
+            err,
+            HandshakeError::Failure(Error::Protocol(ProtocolError::UnsupportedSecWebSocketVersion))
+        ));
+
+        let (_, response) = Response::try_parse(&stream.output).unwrap().unwrap();
+        assert_eq!(response.status(), http::StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(response.headers().get("Sec-WebSocket-Version").unwrap(), "13");
+    }
+
+    #[test]
+    fn malformed_upgrade_headers() {
+        let cases = [
+            ("Upgrade: websocket\r\n", ProtocolError::MissingConnectionUpgradeHeader),
+            (
+                "Connection: keep-alive\r\nUpgrade: websocket\r\n",
+                ProtocolError::MissingConnectionUpgradeHeader,
+            ),
+            ("Connection: Upgrade\r\n", ProtocolError::MissingUpgradeWebSocketHeader),
+            (
+                "Connection: Upgrade\r\nUpgrade: h2c\r\n",
+                ProtocolError::MissingUpgradeWebSocketHeader,
+            ),
+            (
+                "Connection: upgrade\r\nUpgrade: WebSocket\r\nSec-WebSocket-Version: 13\r\n",
+                ProtocolError::MissingSecWebSocketKey,
+            ),
+            (
+                "Connection: upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZQ==\r\n",
+                ProtocolError::InvalidSecWebSocketKey,
+            ),
+            (
+                "Connection: upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                Sec-WebSocket-Key: the sample nonce\r\n",
+                ProtocolError::InvalidSecWebSocketKey,
+            ),
+            (
+                "Connection: upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-WebSocket-Key: c29tZSBvdGhlciBub25jZQ==\r\n",
+                ProtocolError::InvalidHeader(http::header::SEC_WEBSOCKET_KEY),
+            ),
+            (
+                "Connection: upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+                ProtocolError::InvalidHeader(http::header::SEC_WEBSOCKET_VERSION),
+            ),
+        ];
+        for (headers, expected) in cases {
+            let data = format!("GET /script.ws HTTP/1.1\r\nHost: foo.com\r\n{headers}\r\n");
+            let mut stream = MockStream::new(data.as_bytes());
+            match ServerHandshake::start(&mut stream, NoCallback, None).handshake() {
+                Err(HandshakeError::Failure(Error::Protocol(ref e))) if *e == expected => {}
+                other => panic!("unexpected result for {headers:?}: {other:?}"),
+            }
+            let (_, response) = Response::try_parse(&stream.output).unwrap().unwrap();
+            assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn connection_header_values() {
+        for connection in ["keep-alive, Upgrade", "UPGRADE", "keep-alive\r\nConnection: upgrade"] {
+            let data = format!(
+                "GET /script.ws HTTP/1.1\r\nHost: foo.com\r\nConnection: {connection}\r\n\
+                Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+            );
+            let mut stream = MockStream::new(data.as_bytes());
+            ServerHandshake::start(&mut stream, NoCallback, None).handshake().unwrap();
+        }
+    }
 }
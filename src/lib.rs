@@ -45,4 +45,7 @@ pub use crate::{
 };
 
 #[cfg(all(any(feature = "native-tls", feature = "__rustls-tls"), feature = "handshake"))]
-pub use tls::{client_tls, client_tls_with_config, Connector};
+pub use crate::{
+    client::connect_with_connector,
+    tls::{client_tls, client_tls_with_config, Connector},
+};
@@ -188,10 +188,49 @@ pub enum CloseCode {
 }
 
 impl CloseCode {
+    /// 1000, normal closure. Alias of [`CloseCode::Normal`].
+    pub const NORMAL: CloseCode = Normal;
+    /// 1001, the endpoint is going away. Alias of [`CloseCode::Away`].
+    pub const GOING_AWAY: CloseCode = Away;
+    /// 1002, protocol error. Alias of [`CloseCode::Protocol`].
+    pub const PROTOCOL_ERROR: CloseCode = Protocol;
+    /// 1003, unsupported data type. Alias of [`CloseCode::Unsupported`].
+    pub const UNSUPPORTED_DATA: CloseCode = Unsupported;
+    /// 1007, payload inconsistent with the message type. Alias of [`CloseCode::Invalid`].
+    pub const INVALID_PAYLOAD: CloseCode = Invalid;
+    /// 1008, policy violation. Alias of [`CloseCode::Policy`].
+    pub const POLICY_VIOLATION: CloseCode = Policy;
+    /// 1009, message too big. Alias of [`CloseCode::Size`].
+    pub const MESSAGE_TOO_BIG: CloseCode = Size;
+    /// 1010, a required extension was not negotiated. Alias of [`CloseCode::Extension`].
+    pub const MANDATORY_EXTENSION: CloseCode = Extension;
+    /// 1011, unexpected condition on the server. Alias of [`CloseCode::Error`].
+    pub const INTERNAL_ERROR: CloseCode = Error;
+
     /// Check if this CloseCode is allowed.
     pub fn is_allowed(self) -> bool {
         !matches!(self, Bad(_) | Reserved(_) | Status | Abnormal | Tls)
     }
+
+    /// Check if this CloseCode may appear in a close frame at all.
+    ///
+    /// The codes 1005 (`Status`), 1006 (`Abnormal`) and 1015 (`Tls`) are reserved for
+    /// reporting conditions locally and must never be sent over the wire.
+    pub fn is_allowed_on_wire(self) -> bool {
+        !matches!(self, Status | Abnormal | Tls)
+    }
+
+    /// Check if this CloseCode is reserved by the protocol, i.e. it is either one of the codes
+    /// that must not be sent or it is in the range 1000-2999 without a defined meaning.
+    pub fn is_reserved(self) -> bool {
+        matches!(self, Reserved(_) | Status | Abnormal | Tls)
+    }
+
+    /// Check if this CloseCode is in the range 4000-4999 which is reserved for private use
+    /// by applications.
+    pub fn is_private(self) -> bool {
+        matches!(self, Library(_))
+    }
 }
 
 impl fmt::Display for CloseCode {
@@ -250,7 +289,7 @@ impl From<u16> for CloseCode {
             1013 => Again,
             1015 => Tls,
             1..=999 => Bad(code),
-            1016..=2999 => Reserved(code),
+            1004 | 1014 | 1016..=2999 => Reserved(code),
             3000..=3999 => Iana(code),
             4000..=4999 => Library(code),
             _ => Bad(code),
@@ -288,4 +327,56 @@ mod tests {
         assert_eq!(byte, 1001u16);
         assert_eq!(u16::from(text), 1001u16);
     }
+
+    #[test]
+    fn closecode_constants() {
+        let constants = [
+            (CloseCode::NORMAL, 1000),
+            (CloseCode::GOING_AWAY, 1001),
+            (CloseCode::PROTOCOL_ERROR, 1002),
+            (CloseCode::UNSUPPORTED_DATA, 1003),
+            (CloseCode::INVALID_PAYLOAD, 1007),
+            (CloseCode::POLICY_VIOLATION, 1008),
+            (CloseCode::MESSAGE_TOO_BIG, 1009),
+            (CloseCode::MANDATORY_EXTENSION, 1010),
+            (CloseCode::INTERNAL_ERROR, 1011),
+        ];
+        for (constant, code) in constants {
+            assert_eq!(u16::from(constant), code);
+            assert_eq!(CloseCode::from(code), constant);
+        }
+    }
+
+    #[test]
+    fn closecode_roundtrip() {
+        for code in 0..=u16::MAX {
+            assert_eq!(u16::from(CloseCode::from(code)), code);
+        }
+    }
+
+    #[test]
+    fn closecode_ranges() {
+        assert!(CloseCode::from(1004).is_reserved());
+        assert!(CloseCode::from(1005).is_reserved());
+        assert!(CloseCode::from(2999).is_reserved());
+        assert!(!CloseCode::from(1000).is_reserved());
+        assert!(!CloseCode::from(3000).is_reserved());
+        assert!(!CloseCode::from(999).is_reserved());
+
+        assert!(CloseCode::from(4000).is_private());
+        assert!(CloseCode::from(4999).is_private());
+        assert!(!CloseCode::from(3999).is_private());
+        assert!(!CloseCode::from(5000).is_private());
+    }
+
+    #[test]
+    fn closecode_on_wire() {
+        for code in [1005u16, 1006, 1015] {
+            let code = CloseCode::from(code);
+            assert!(!code.is_allowed_on_wire());
+            assert!(!code.is_allowed());
+        }
+        assert!(CloseCode::Normal.is_allowed_on_wire());
+        assert!(CloseCode::from(3000).is_allowed_on_wire());
+    }
 }
@@ -14,7 +14,10 @@ use super::{
     coding::{CloseCode, Control, Data, OpCode},
     mask::{apply_mask, generate_mask},
 };
-use crate::error::{Error, ProtocolError, Result};
+use crate::{
+    error::{Error, ProtocolError, Result},
+    protocol::Role,
+};
 
 /// A struct representing the close command.
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -171,6 +174,10 @@ impl FrameHeader {
                     Err(err) => {
                         return Err(err.into());
                     }
+                    // The most significant bit of a 64-bit length MUST be 0. (RFC 6455)
+                    Ok(read) if read >> 63 != 0 => {
+                        return Err(Error::Protocol(ProtocolError::InvalidLength));
+                    }
                     Ok(read) => read,
                 }
             } else {
@@ -255,15 +262,6 @@ impl Frame {
         self.header.mask.is_some()
     }
 
-    /// Generate a random mask for the frame.
-    ///
-    /// This just generates a mask, payload is not changed. The actual masking is performed
-    /// either on `format()` or on `apply_mask()` call.
-    #[inline]
-    pub(crate) fn set_random_mask(&mut self) {
-        self.header.set_random_mask();
-    }
-
     /// This method unmasks the payload and should only be called on frames that are actually
     /// masked. In other words, those frames that have just been received from a client endpoint.
     #[inline]
@@ -366,6 +364,43 @@ impl Frame {
         output.write_all(self.payload())?;
         Ok(())
     }
+
+    /// Encode the frame as it would be sent by an endpoint with the given role.
+    ///
+    /// Frames sent by a client are masked, with the mask in the header or a random one if there
+    /// is none. Frames sent by a server are never masked.
+    pub fn to_bytes(&self, role: Role) -> Vec<u8> {
+        let mut header = self.header.clone();
+        match role {
+            Role::Client if header.mask.is_none() => header.set_random_mask(),
+            Role::Client => {}
+            Role::Server => header.mask = None,
+        }
+        let mut output = Vec::with_capacity(self.len());
+        Frame { header, payload: self.payload.clone() }
+            .format(&mut output)
+            .expect("Bug: can't write to vector");
+        output
+    }
+
+    /// Decode the first frame contained in `bytes`, returning it along with the number of bytes
+    /// it took up.
+    ///
+    /// The payload of a masked frame is unmasked. If `bytes` does not contain a complete frame,
+    /// an [`UnexpectedEof`](ErrorKind::UnexpectedEof) error is returned.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Frame, usize)> {
+        let mut cursor = Cursor::new(bytes);
+        let (header, length) = FrameHeader::parse(&mut cursor)?
+            .ok_or_else(|| Error::Io(ErrorKind::UnexpectedEof.into()))?;
+        let start = cursor.position() as usize;
+        if length > (bytes.len() - start) as u64 {
+            return Err(Error::Io(ErrorKind::UnexpectedEof.into()));
+        }
+        let end = start + length as usize;
+        let mut frame = Frame::from_payload(header, bytes[start..end].to_vec());
+        frame.apply_mask();
+        Ok((frame, end))
+    }
 }
 
 impl fmt::Display for Frame {
@@ -469,6 +504,64 @@ mod tests {
         assert_eq!(frame.into_data(), vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]);
     }
 
+    #[test]
+    fn bytes_roundtrip() {
+        // Payload lengths with 7-bit, 16-bit and 64-bit length encodings.
+        for (length, header_len) in [(0, 2), (125, 2), (126, 4), (65535, 4), (65536, 10)] {
+            let payload: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let frame = Frame::message(payload.clone(), OpCode::Data(Data::Binary), true);
+
+            let bytes = frame.to_bytes(Role::Server);
+            assert_eq!(bytes.len(), header_len + length);
+            assert_eq!(bytes[1] & 0x80, 0);
+            assert_eq!(&bytes[header_len..], &payload[..]);
+            let (decoded, consumed) = Frame::from_bytes(&bytes).unwrap();
+            assert_eq!(consumed, bytes.len());
+            assert_eq!(decoded.header(), frame.header());
+            assert_eq!(decoded.payload(), &payload[..]);
+
+            let mut bytes = frame.to_bytes(Role::Client);
+            assert_eq!(bytes.len(), header_len + 4 + length);
+            assert_eq!(bytes[1] & 0x80, 0x80);
+            bytes.extend_from_slice(&[0x89, 0x00]);
+            let (decoded, consumed) = Frame::from_bytes(&bytes).unwrap();
+            assert_eq!(consumed, bytes.len() - 2);
+            assert_eq!(decoded.header(), frame.header());
+            assert_eq!(decoded.payload(), &payload[..]);
+        }
+    }
+
+    #[test]
+    fn bytes_incomplete() {
+        for bytes in [&[][..], &[0x82], &[0x82, 0x7e, 0x01], &[0x82, 0x03, 0x01, 0x02]] {
+            match Frame::from_bytes(bytes) {
+                Err(Error::Io(err)) => assert_eq!(err.kind(), ErrorKind::UnexpectedEof),
+                other => panic!("unexpected result: {other:?}"),
+            }
+        }
+        assert!(matches!(
+            Frame::from_bytes(&[0x83, 0x00]),
+            Err(Error::Protocol(ProtocolError::InvalidOpcode(3)))
+        ));
+    }
+
+    #[test]
+    fn parse_64bit_length() {
+        let mut raw = Cursor::new([0x82, 0x7f, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        let (_, length) = FrameHeader::parse(&mut raw).unwrap().unwrap();
+        assert_eq!(length, i64::MAX as u64);
+
+        let mut raw = Cursor::new([0x82, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]);
+        let (_, length) = FrameHeader::parse(&mut raw).unwrap().unwrap();
+        assert_eq!(length, 65536);
+
+        let mut raw = Cursor::new([0x82, 0x7f, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(matches!(
+            FrameHeader::parse(&mut raw),
+            Err(Error::Protocol(ProtocolError::InvalidLength))
+        ));
+    }
+
     #[test]
     fn format() {
         let frame = Frame::ping(vec![0x01, 0x02]);
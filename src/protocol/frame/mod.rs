@@ -7,7 +7,8 @@ mod frame;
 mod mask;
 
 use crate::{
-    error::{CapacityError, Error, Result},
+    error::{CapacityError, Error, ProtocolError, Result},
+    protocol::WebSocketConfig,
     Message, ReadBuffer,
 };
 use log::*;
@@ -15,7 +16,16 @@ use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
 
 pub use self::frame::{CloseFrame, Frame, FrameHeader};
 
+use self::{coding::OpCode, mask::apply_mask};
+
 /// A reader and writer for WebSocket frames.
+///
+/// Unlike [`WebSocket`](crate::WebSocket), this returns every frame exactly in the order it
+/// was received: fragments are not reassembled, control frames are not answered and the
+/// payload of a masked frame is not unmasked. This makes it suitable for proxies that forward
+/// frames as they are. An established connection can be continued frame by frame by passing
+/// the parts returned by [`WebSocket::into_inner`](crate::WebSocket::into_inner) to
+/// [`from_partially_read`](Self::from_partially_read).
 #[derive(Debug)]
 pub struct FrameSocket<Stream> {
     /// The underlying network stream.
@@ -37,7 +47,7 @@ impl<Stream> FrameSocket<Stream> {
 
     /// Extract a stream from the socket.
     pub fn into_inner(self) -> (Stream, Vec<u8>) {
-        (self.stream, self.codec.in_buffer.into_vec())
+        (self.stream, self.codec.into_in_buffer())
     }
 
     /// Returns a shared reference to the inner stream.
@@ -85,9 +95,34 @@ where
 
     /// Flush writes.
     pub fn flush(&mut self) -> Result<()> {
-        self.codec.write_out_buffer(&mut self.stream)?;
-        Ok(self.stream.flush()?)
+        self.codec.flush(&mut self.stream)
+    }
+}
+
+/// Decode all frames contained in `bytes` as a server would receive them.
+///
+/// Client frames are unmasked; unmasked frames are rejected unless
+/// [`WebSocketConfig::accept_unmasked_frames`] is set. Frame sizes are limited by
+/// [`WebSocketConfig::max_frame_size`]. Trailing bytes that do not form a complete
+/// frame result in an [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) error.
+///
+/// This never panics on malformed input, which makes it suitable as a fuzzing target.
+pub fn decode_frames(bytes: &[u8], config: &WebSocketConfig) -> Result<Vec<Frame>> {
+    let mut socket = FrameSocket::new(bytes);
+    let mut frames = Vec::new();
+    while let Some(mut frame) = socket.read(config.max_frame_size)? {
+        if frame.header().mask.is_some() {
+            frame.apply_mask();
+        } else if !config.accept_unmasked_frames {
+            return Err(Error::Protocol(ProtocolError::UnmaskedFrameFromClient));
+        }
+        frames.push(frame);
+    }
+    let rest = socket.codec.in_buffer.as_cursor();
+    if socket.codec.header.is_some() || rest.position() < rest.get_ref().len() as u64 {
+        return Err(Error::Io(IoErrorKind::UnexpectedEof.into()));
     }
+    Ok(frames)
 }
 
 /// A codec for WebSocket frames.
@@ -107,6 +142,8 @@ pub(super) struct FrameCodec {
     out_buffer_write_len: usize,
     /// Header and remaining size of the incoming packet being processed.
     header: Option<(FrameHeader, u64)>,
+    /// Data was written to the stream since it was last flushed.
+    needs_flush: bool,
 }
 
 impl FrameCodec {
@@ -118,6 +155,7 @@ impl FrameCodec {
             max_out_buffer_len: usize::MAX,
             out_buffer_write_len: 0,
             header: None,
+            needs_flush: false,
         }
     }
 
@@ -129,9 +167,36 @@ impl FrameCodec {
             max_out_buffer_len: usize::MAX,
             out_buffer_write_len: 0,
             header: None,
+            needs_flush: false,
+        }
+    }
+
+    /// Consume the codec, returning the data read from the stream that is not processed yet.
+    ///
+    /// The header of a partially received frame is parsed already, so it is encoded again.
+    pub(super) fn into_in_buffer(self) -> Vec<u8> {
+        let rest = self.in_buffer.into_vec();
+        match self.header {
+            Some((header, length)) => {
+                let mut data = Vec::with_capacity(14 + rest.len());
+                header.format(length, &mut data).expect("Bug: can't write to vector");
+                data.extend_from_slice(&rest);
+                data
+            }
+            None => rest,
         }
     }
 
+    /// Check if data was written to the stream without flushing it afterwards.
+    pub(super) fn needs_flush(&self) -> bool {
+        self.needs_flush
+    }
+
+    /// Get the number of bytes waiting in the out buffer.
+    pub(super) fn out_buffer_len(&self) -> usize {
+        self.out_buffer.len()
+    }
+
     /// Sets a maximum size for the out buffer.
     pub(super) fn set_max_out_buffer_len(&mut self, max: usize) {
         self.max_out_buffer_len = max;
@@ -162,8 +227,12 @@ impl FrameCodec {
                     self.header = FrameHeader::parse(cursor)?;
                 }
 
-                if let Some((_, ref length)) = self.header {
-                    let length = *length;
+                if let Some((ref header, length)) = self.header {
+                    // Control frames must have a payload length of 125 bytes or less.
+                    // (RFC 6455) Reject them before waiting for the payload.
+                    if matches!(header.opcode, OpCode::Control(_)) && length > 125 {
+                        return Err(Error::Protocol(ProtocolError::ControlFrameTooBig));
+                    }
 
                     // Enforce frame size limit early and make sure `length`
                     // is not too big (fits into `usize`).
@@ -228,6 +297,44 @@ impl FrameCodec {
         }
     }
 
+    /// Writes a frame built from `header` and a borrowed `payload` into the `out_buffer`.
+    ///
+    /// Behaves like [`Self::buffer_frame`] but copies the payload straight into the
+    /// buffer, so the caller keeps ownership of it. The payload is only cloned if the
+    /// buffer is full and [`Error::WriteBufferFull`] is returned.
+    pub(super) fn buffer_frame_ref<Stream>(
+        &mut self,
+        stream: &mut Stream,
+        header: &FrameHeader,
+        payload: &[u8],
+    ) -> Result<()>
+    where
+        Stream: Write,
+    {
+        let length = payload.len() as u64;
+        let len = header.len(length) + payload.len();
+        if len + self.out_buffer.len() > self.max_out_buffer_len {
+            let frame = Frame::from_payload(header.clone(), payload.to_vec());
+            return Err(Error::WriteBufferFull(Message::Frame(frame)));
+        }
+
+        trace!("writing frame {header:?} with {length} bytes of payload");
+
+        self.out_buffer.reserve(len);
+        header.format(length, &mut self.out_buffer).expect("Bug: can't write to vector");
+        let start = self.out_buffer.len();
+        self.out_buffer.extend_from_slice(payload);
+        if let Some(mask) = header.mask {
+            apply_mask(&mut self.out_buffer[start..], mask);
+        }
+
+        if self.out_buffer.len() > self.out_buffer_write_len {
+            self.write_out_buffer(stream)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Writes the out_buffer to the provided stream.
     ///
     /// Does **not** flush.
@@ -246,10 +353,22 @@ impl FrameCodec {
                 .into());
             }
             self.out_buffer.drain(0..len);
+            self.needs_flush = true;
         }
 
         Ok(())
     }
+
+    /// Writes the out_buffer to the provided stream and flushes it.
+    pub(super) fn flush<Stream>(&mut self, stream: &mut Stream) -> Result<()>
+    where
+        Stream: Write,
+    {
+        self.write_out_buffer(stream)?;
+        stream.flush()?;
+        self.needs_flush = false;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -257,7 +376,8 @@ mod tests {
 
     use crate::error::{CapacityError, Error};
 
-    use super::{Frame, FrameSocket};
+    use super::{decode_frames, Frame, FrameSocket};
+    use crate::{error::ProtocolError, protocol::WebSocketConfig, stream::ReadWrite};
 
     use std::io::Cursor;
 
@@ -280,6 +400,62 @@ mod tests {
         assert_eq!(rest, vec![0x99]);
     }
 
+    #[test]
+    fn decode() {
+        let config = WebSocketConfig::default();
+        let frames = decode_frames(
+            &[0x81, 0x82, 0x01, 0x02, 0x03, 0x04, b'H' ^ 0x01, b'i' ^ 0x02, 0x89, 0x80, 0, 0, 0, 0],
+            &config,
+        )
+        .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].payload(), b"Hi");
+        assert!(frames[1].payload().is_empty());
+
+        assert!(decode_frames(&[], &config).unwrap().is_empty());
+        assert!(matches!(
+            decode_frames(&[0x82, 0x01, 0x00], &config),
+            Err(Error::Protocol(ProtocolError::UnmaskedFrameFromClient))
+        ));
+        let unmasked = WebSocketConfig { accept_unmasked_frames: true, ..config };
+        assert_eq!(decode_frames(&[0x82, 0x01, 0x00], &unmasked).unwrap().len(), 1);
+
+        // Truncated header and truncated payload.
+        for bytes in [&[0x82][..], &[0x82, 0x84, 0, 0, 0, 0, 0x01]] {
+            assert!(matches!(decode_frames(bytes, &config), Err(Error::Io(_))));
+        }
+        // Declared length far beyond the frame size limit.
+        let huge = [0x82, 0xff, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0];
+        assert!(matches!(decode_frames(&huge, &config), Err(Error::Capacity(_))));
+    }
+
+    #[test]
+    fn wire_order() {
+        let raw = vec![
+            0x01, 0x02, b'H', b'e', // text, not final
+            0x89, 0x01, 0x01, // ping
+            0x00, 0x01, b'l', // continuation
+            0x8a, 0x00, // pong
+            0x80, 0x02, b'l', b'o', // final continuation
+            0x88, 0x02, 0x03, 0xe8, // close
+        ];
+        let mut sock = FrameSocket::new(ReadWrite::new(Cursor::new(raw.clone()), Vec::<u8>::new()));
+        let mut frames = Vec::new();
+        while let Some(frame) = sock.read(None).unwrap() {
+            frames.push(frame);
+        }
+        let opcodes: Vec<u8> = frames.iter().map(|f| f.header().opcode.into()).collect();
+        assert_eq!(opcodes, [1, 9, 0, 10, 0, 8]);
+        // Nothing was answered.
+        assert!(sock.get_ref().writer().is_empty());
+
+        let mut forwarded = Vec::new();
+        for frame in frames {
+            frame.format(&mut forwarded).unwrap();
+        }
+        assert_eq!(forwarded, raw);
+    }
+
     #[test]
     fn from_partially_read() {
         let raw = Cursor::new(vec![0x02, 0x03, 0x04, 0x05, 0x06, 0x07]);
@@ -313,6 +489,22 @@ mod tests {
         let _ = sock.read(None); // should not crash
     }
 
+    #[test]
+    fn large_length() {
+        let mut raw = vec![0x82, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+        raw.resize(raw.len() + 65536, 0x01);
+        let mut sock = FrameSocket::new(Cursor::new(raw));
+        assert_eq!(sock.read(Some(1 << 20)).unwrap().unwrap().payload().len(), 65536);
+
+        // The largest valid length is rejected by the size limit before reading the payload.
+        let raw = vec![0x82, 0x7f, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let mut sock = FrameSocket::new(Cursor::new(raw));
+        assert!(matches!(
+            sock.read(Some(1 << 20)),
+            Err(Error::Capacity(CapacityError::MessageTooLong { max_size: 0x100000, .. }))
+        ));
+    }
+
     #[test]
     fn size_limit_hit() {
         let raw = Cursor::new(vec![0x82, 0x07, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]);
@@ -322,4 +514,22 @@ mod tests {
             Err(Error::Capacity(CapacityError::MessageTooLong { size: 7, max_size: 5 }))
         ));
     }
+
+    #[test]
+    fn control_frame_too_big() {
+        // Only the headers are sent, the frames are rejected before their payload arrives.
+        for header in [&[0x89, 0x7e, 0x00, 0x7e][..], &[0x88, 0x7e, 0x00, 0xc8]] {
+            let mut sock = FrameSocket::new(Cursor::new(header.to_vec()));
+            assert!(matches!(
+                sock.read(None),
+                Err(Error::Protocol(ProtocolError::ControlFrameTooBig))
+            ));
+        }
+
+        // 125 bytes is still fine.
+        let mut raw = vec![0x8a, 0x7d];
+        raw.extend_from_slice(&[0; 125]);
+        let mut sock = FrameSocket::new(Cursor::new(raw));
+        assert_eq!(sock.read(None).unwrap().unwrap().payload().len(), 125);
+    }
 }
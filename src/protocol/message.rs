@@ -79,6 +79,7 @@ use self::string_collect::StringCollector;
 #[derive(Debug)]
 pub struct IncompleteMessage {
     collector: IncompleteMessageCollector,
+    fragments: usize,
 }
 
 #[derive(Debug)]
@@ -97,9 +98,15 @@ impl IncompleteMessage {
                     IncompleteMessageCollector::Text(StringCollector::new())
                 }
             },
+            fragments: 0,
         }
     }
 
+    /// Get the number of fragments the message has been extended with so far.
+    pub fn fragments(&self) -> usize {
+        self.fragments
+    }
+
     /// Get the current filled size of the buffer.
     pub fn len(&self) -> usize {
         match self.collector {
@@ -123,6 +130,7 @@ impl IncompleteMessage {
             }));
         }
 
+        self.fragments += 1;
         match self.collector {
             IncompleteMessageCollector::Binary(ref mut v) => {
                 v.extend(tail.as_ref());
@@ -168,6 +176,10 @@ pub enum Message {
     /// A close message with the optional close frame.
     Close(Option<CloseFrame<'static>>),
     /// Raw frame. Note, that you're not going to get this value while reading the message.
+    ///
+    /// It is written as is, including the reserved bits of its header, e.g. to develop an
+    /// extension. Peers reject frames with reserved bits set unless they negotiated an
+    /// extension defining them, and so does tungstenite.
     Frame(Frame),
 }
 
@@ -268,6 +280,27 @@ impl Message {
             Message::Frame(ref frame) => Ok(frame.to_text()?),
         }
     }
+
+    /// Get the payload of a binary message without consuming it.
+    ///
+    /// Returns `None` for all other messages, including ping, pong and close messages.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Message::Binary(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Get the payload of a text message without consuming it.
+    ///
+    /// Returns `None` for all other messages, including ping, pong and close messages.
+    /// Unlike [`to_text`](Self::to_text), binary data is never converted.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Message::Text(ref string) => Some(string),
+            _ => None,
+        }
+    }
 }
 
 impl From<String> for Message {
@@ -356,6 +389,22 @@ mod tests {
         assert_eq!(bin_copy, serialized);
     }
 
+    #[test]
+    fn borrowed_payload() {
+        let text = Message::text("hello");
+        assert_eq!(text.as_str(), Some("hello"));
+        assert_eq!(text.as_bytes(), None);
+
+        let bin = Message::binary(vec![1, 2, 3]);
+        assert_eq!(bin.as_bytes(), Some(&[1u8, 2, 3][..]));
+        assert_eq!(bin.as_str(), None);
+
+        for msg in [Message::Ping(vec![1]), Message::Pong(vec![1]), Message::Close(None)] {
+            assert_eq!(msg.as_bytes(), None);
+            assert_eq!(msg.as_str(), None);
+        }
+    }
+
     #[test]
     fn text_convert() {
         let s = "kiwotsukete";
@@ -3,21 +3,29 @@
 pub mod frame;
 
 mod message;
+mod reader;
 
-pub use self::{frame::CloseFrame, message::Message};
+pub use self::{frame::CloseFrame, message::Message, reader::MessageReader};
 
 use self::{
     frame::{
         coding::{CloseCode, Control as OpCtl, Data as OpData, OpCode},
-        Frame, FrameCodec,
+        Frame, FrameCodec, FrameHeader,
     },
     message::{IncompleteMessage, IncompleteMessageType},
 };
-use crate::error::{Error, ProtocolError, Result};
+#[cfg(feature = "tcp-keepalive")]
+use crate::stream::TcpKeepalive;
+use crate::{
+    error::{CapacityError, Error, ProtocolError, Result},
+    stream::{ReadWrite, ShutdownWrite},
+};
 use log::*;
 use std::{
+    fmt,
     io::{self, Read, Write},
     mem::replace,
+    time::{Duration, Instant},
 };
 
 /// Indicates a Client or Server role of the websocket
@@ -30,6 +38,9 @@ pub enum Role {
 }
 
 /// The configuration for WebSocket connection.
+///
+/// It can be created with a struct literal based on [`WebSocketConfig::default`] or with
+/// [`WebSocketConfig::builder`].
 #[derive(Debug, Clone, Copy)]
 pub struct WebSocketConfig {
     /// Does nothing, instead use `max_write_buffer_size`.
@@ -70,6 +81,64 @@ pub struct WebSocketConfig {
     /// some popular libraries that are sending unmasked frames, ignoring the RFC.
     /// By default this option is set to `false`, i.e. according to RFC 6455.
     pub accept_unmasked_frames: bool,
+    /// The maximum time to wait for the peer to complete the close handshake after our close
+    /// frame, or our reply to the peer's close frame, has been queued. `None` means waiting
+    /// indefinitely, which is the default.
+    ///
+    /// Once the timeout elapses, [`read`](WebSocket::read) or [`tick`](WebSocket::tick) returns
+    /// [`Error::ConnectionClosed`] and the underlying connection may be dropped. Note that the
+    /// timeout is only checked when `read` starts or `tick` is called, so with a blocking stream
+    /// a read timeout should be set on the stream as well, otherwise `read` may block waiting
+    /// for a peer that never answers.
+    pub close_timeout: Option<Duration>,
+    /// The maximum number of frames a single incoming fragmented message may consist of. `None`
+    /// means no limit, which is the default. This complements
+    /// [`max_message_size`](Self::max_message_size) by preventing a peer from splitting a
+    /// message into a huge number of tiny frames.
+    pub max_fragments: Option<usize>,
+    /// When set to `true`, a close frame is sent to the peer before [`read`](WebSocket::read)
+    /// returns an error caused by the peer: code 1002 for protocol errors, 1007 for invalid
+    /// UTF-8 and 1009 for messages or frames exceeding the size limits. This is what RFC 6455
+    /// calls _Failing the WebSocket Connection_ and what the Autobahn TestSuite checks for.
+    /// The default is `false`, meaning the error is only returned.
+    ///
+    /// Note that the other checks mandated by the RFC, e.g. rejecting reserved opcodes,
+    /// fragmented control frames or invalid close codes, are always enabled.
+    pub close_on_error: bool,
+    /// When set to `true`, empty text and binary messages are silently dropped instead of
+    /// being returned by [`read`](WebSocket::read). They are valid per RFC 6455, so by default
+    /// they are delivered as `Message::Text("")` and `Message::Binary(vec![])`.
+    pub skip_empty_messages: bool,
+    /// The maximum size of the body attached to [`Error::Http`] when a server rejects the
+    /// client handshake. `None` means no limit, which is the default.
+    ///
+    /// The client never waits for the body of an error response, only the data received along
+    /// with the response head is attached, so the body may be incomplete either way. The head
+    /// and that data are bounded by the handshake size limits.
+    pub max_handshake_error_body_size: Option<usize>,
+    /// When set to `true`, a close frame received from the peer is answered automatically with
+    /// a close frame echoing its code and reason, completing the close handshake. This is the
+    /// default.
+    ///
+    /// When set to `false`, the application must answer by calling
+    /// [`close`](WebSocket::close) after receiving the [`Message::Close`]. Close frames with
+    /// an invalid code are still answered automatically with a protocol error. Closing the
+    /// connection when the peer violates the protocol is controlled by
+    /// [`close_on_error`](Self::close_on_error) instead.
+    pub auto_close: bool,
+    /// The maximum time without receiving any frame, data or control, after which
+    /// [`tick`](WebSocket::tick) closes the connection with code 1001. `None` means no limit,
+    /// which is the default.
+    pub idle_timeout: Option<Duration>,
+    /// TCP keepalive parameters applied by [`connect`](crate::connect) and
+    /// [`connect_with_config`](crate::client::connect_with_config) to the TCP connection before
+    /// the handshake. `None` leaves keepalive disabled, which is the default.
+    ///
+    /// Streams passed in by the user, e.g. to [`client`](crate::client()) or
+    /// [`accept`](crate::accept()), are not touched. See [`TcpKeepalive`] for the parameters
+    /// supported on each platform.
+    #[cfg(feature = "tcp-keepalive")]
+    pub tcp_keepalive: Option<TcpKeepalive>,
 }
 
 impl Default for WebSocketConfig {
@@ -82,11 +151,40 @@ impl Default for WebSocketConfig {
             max_message_size: Some(64 << 20),
             max_frame_size: Some(16 << 20),
             accept_unmasked_frames: false,
+            close_timeout: None,
+            max_fragments: None,
+            close_on_error: false,
+            skip_empty_messages: false,
+            max_handshake_error_body_size: None,
+            auto_close: true,
+            idle_timeout: None,
+            #[cfg(feature = "tcp-keepalive")]
+            tcp_keepalive: None,
         }
     }
 }
 
 impl WebSocketConfig {
+    /// Start building a configuration from the default values.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use tungstenite::protocol::WebSocketConfig;
+    ///
+    /// let config = WebSocketConfig::builder()
+    ///     .with_max_message_size(Some(1 << 20))
+    ///     .with_close_timeout(Some(Duration::from_secs(5)))
+    ///     .with_close_on_error(true)
+    ///     .build();
+    /// assert_eq!(config.max_message_size, Some(1 << 20));
+    /// ```
+    #[must_use]
+    pub fn builder() -> WebSocketConfigBuilder {
+        WebSocketConfigBuilder::default()
+    }
+
     /// Panic if values are invalid.
     pub(crate) fn assert_valid(&self) {
         assert!(
@@ -97,6 +195,142 @@ impl WebSocketConfig {
     }
 }
 
+/// Builder for a [`WebSocketConfig`], created by [`WebSocketConfig::builder`].
+///
+/// Options which are not set keep their default value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebSocketConfigBuilder {
+    config: WebSocketConfig,
+}
+
+impl WebSocketConfigBuilder {
+    /// Sets [`write_buffer_size`](WebSocketConfig::write_buffer_size).
+    pub fn with_write_buffer_size(mut self, write_buffer_size: usize) -> Self {
+        self.config.write_buffer_size = write_buffer_size;
+        self
+    }
+
+    /// Sets [`max_write_buffer_size`](WebSocketConfig::max_write_buffer_size).
+    pub fn with_max_write_buffer_size(mut self, max_write_buffer_size: usize) -> Self {
+        self.config.max_write_buffer_size = max_write_buffer_size;
+        self
+    }
+
+    /// Sets [`max_message_size`](WebSocketConfig::max_message_size).
+    pub fn with_max_message_size(mut self, max_message_size: Option<usize>) -> Self {
+        self.config.max_message_size = max_message_size;
+        self
+    }
+
+    /// Sets [`max_frame_size`](WebSocketConfig::max_frame_size).
+    pub fn with_max_frame_size(mut self, max_frame_size: Option<usize>) -> Self {
+        self.config.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Sets [`accept_unmasked_frames`](WebSocketConfig::accept_unmasked_frames).
+    pub fn with_accept_unmasked_frames(mut self, accept_unmasked_frames: bool) -> Self {
+        self.config.accept_unmasked_frames = accept_unmasked_frames;
+        self
+    }
+
+    /// Sets [`close_timeout`](WebSocketConfig::close_timeout).
+    pub fn with_close_timeout(mut self, close_timeout: Option<Duration>) -> Self {
+        self.config.close_timeout = close_timeout;
+        self
+    }
+
+    /// Sets [`max_fragments`](WebSocketConfig::max_fragments).
+    pub fn with_max_fragments(mut self, max_fragments: Option<usize>) -> Self {
+        self.config.max_fragments = max_fragments;
+        self
+    }
+
+    /// Sets [`close_on_error`](WebSocketConfig::close_on_error).
+    pub fn with_close_on_error(mut self, close_on_error: bool) -> Self {
+        self.config.close_on_error = close_on_error;
+        self
+    }
+
+    /// Sets [`skip_empty_messages`](WebSocketConfig::skip_empty_messages).
+    pub fn with_skip_empty_messages(mut self, skip_empty_messages: bool) -> Self {
+        self.config.skip_empty_messages = skip_empty_messages;
+        self
+    }
+
+    /// Sets [`max_handshake_error_body_size`](WebSocketConfig::max_handshake_error_body_size).
+    pub fn with_max_handshake_error_body_size(
+        mut self,
+        max_handshake_error_body_size: Option<usize>,
+    ) -> Self {
+        self.config.max_handshake_error_body_size = max_handshake_error_body_size;
+        self
+    }
+
+    /// Sets [`auto_close`](WebSocketConfig::auto_close).
+    pub fn with_auto_close(mut self, auto_close: bool) -> Self {
+        self.config.auto_close = auto_close;
+        self
+    }
+
+    /// Sets [`idle_timeout`](WebSocketConfig::idle_timeout).
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets [`tcp_keepalive`](WebSocketConfig::tcp_keepalive).
+    #[cfg(feature = "tcp-keepalive")]
+    pub fn with_tcp_keepalive(mut self, tcp_keepalive: Option<TcpKeepalive>) -> Self {
+        self.config.tcp_keepalive = tcp_keepalive;
+        self
+    }
+
+    /// Returns the configuration.
+    #[must_use]
+    pub fn build(self) -> WebSocketConfig {
+        self.config
+    }
+}
+
+/// Control frame activity reported to [`WebSocket::set_on_control_event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlEvent<'a> {
+    /// A ping with the given payload was received.
+    PingReceived(&'a [u8]),
+    /// A pong with the given payload was received.
+    PongReceived(&'a [u8]),
+    /// A pong answering a received ping was queued for sending.
+    AutoPongSent(&'a [u8]),
+    /// A close frame was received.
+    CloseReceived(Option<&'a CloseFrame<'a>>),
+}
+
+/// Raw handshake bytes reported to
+/// [`MidHandshake::set_on_handshake_data`](crate::handshake::MidHandshake::set_on_handshake_data).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeData<'a> {
+    /// The request or response sent to the peer, reported once it has been fully written.
+    Sent(&'a [u8]),
+    /// The request or response received from the peer, excluding any data that followed it.
+    Received(&'a [u8]),
+}
+
+/// A callback supplied by the user, kept by the connection it was set on.
+pub(crate) struct Hook<F: ?Sized>(pub(crate) Box<F>);
+
+impl<F: ?Sized> fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Hook(..)")
+    }
+}
+
+/// See [`WebSocket::set_on_control_event`].
+type OnControlEvent = dyn FnMut(ControlEvent<'_>) + Send + Sync;
+
+/// See [`WebSocket::set_masking_key_provider`].
+type MaskingKeyProvider = dyn FnMut(u64) -> [u8; 4] + Send + Sync;
+
 /// WebSocket input-output stream.
 ///
 /// This is THE structure you want to create to be able to speak the WebSocket protocol.
@@ -109,6 +343,11 @@ pub struct WebSocket<Stream> {
     socket: Stream,
     /// The context for managing a WebSocket.
     context: WebSocketContext,
+    /// The subprotocol selected during the handshake.
+    subprotocol: Option<String>,
+    /// An error encountered by [`read_batch`](Self::read_batch) after some messages were read,
+    /// returned by the next read.
+    pending_error: Option<Error>,
 }
 
 impl<Stream> WebSocket<Stream> {
@@ -121,7 +360,12 @@ impl<Stream> WebSocket<Stream> {
     /// # Panics
     /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`.
     pub fn from_raw_socket(stream: Stream, role: Role, config: Option<WebSocketConfig>) -> Self {
-        WebSocket { socket: stream, context: WebSocketContext::new(role, config) }
+        WebSocket {
+            socket: stream,
+            context: WebSocketContext::new(role, config),
+            subprotocol: None,
+            pending_error: None,
+        }
     }
 
     /// Convert a raw socket into a WebSocket without performing a handshake.
@@ -141,9 +385,35 @@ impl<Stream> WebSocket<Stream> {
         WebSocket {
             socket: stream,
             context: WebSocketContext::from_partially_read(part, role, config),
+            subprotocol: None,
+            pending_error: None,
         }
     }
 
+    /// Returns the subprotocol selected by the server during the handshake, if any.
+    ///
+    /// This is always `None` for WebSockets created without a handshake by this crate.
+    /// No extensions are ever negotiated, so there is no equivalent accessor for
+    /// `Sec-WebSocket-Extensions`.
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.subprotocol.as_deref()
+    }
+
+    /// Record the subprotocol selected during the handshake.
+    #[cfg(feature = "handshake")]
+    pub(crate) fn set_subprotocol(&mut self, subprotocol: Option<String>) {
+        self.subprotocol = subprotocol;
+    }
+
+    /// Extract the stream from the WebSocket, along with any data that was already read from it
+    /// but not processed yet (e.g. frames the peer sent right after the handshake). This includes
+    /// a partially received frame, but not the complete fragments of an unfinished message.
+    ///
+    /// Data queued for writing but not yet flushed is lost.
+    pub fn into_inner(self) -> (Stream, Vec<u8>) {
+        (self.socket, self.context.frame.into_in_buffer())
+    }
+
     /// Returns a shared reference to the inner stream.
     pub fn get_ref(&self) -> &Stream {
         &self.socket
@@ -166,6 +436,11 @@ impl<Stream> WebSocket<Stream> {
         self.context.get_config()
     }
 
+    /// Get the role of this endpoint, which tells whether outgoing frames are masked.
+    pub fn role(&self) -> Role {
+        self.context.role()
+    }
+
     /// Check if it is possible to read messages.
     ///
     /// Reading is impossible after receiving `Message::Close`. It is still possible after
@@ -180,6 +455,61 @@ impl<Stream> WebSocket<Stream> {
     pub fn can_write(&self) -> bool {
         self.context.can_write()
     }
+
+    /// Check if there is data that is not written and flushed to the stream yet.
+    ///
+    /// This is the case after [`write`](Self::write) without a successful
+    /// [`flush`](Self::flush), even if the written data already reached the stream because the
+    /// write buffer was full, or when an automatic pong or close reply is queued. Keep calling
+    /// `flush` until this returns `false` to make sure everything was sent, e.g. before shutting
+    /// down the connection.
+    pub fn has_pending_writes(&self) -> bool {
+        self.context.has_pending_writes()
+    }
+
+    /// Set a function called on control frame activity, e.g. to collect metrics about pings
+    /// and pongs or to track liveness of the peer. It replaces the previously set one.
+    ///
+    /// The function is owned by this WebSocket, so it may keep per-connection state.
+    pub fn set_on_control_event(
+        &mut self,
+        callback: impl FnMut(ControlEvent<'_>) + Send + Sync + 'static,
+    ) {
+        self.context.set_on_control_event(callback);
+    }
+
+    /// Set a function providing the masking key of each frame sent by a client, given the
+    /// number of frames masked before. By default a random key is generated for every frame.
+    ///
+    /// The function is owned by this WebSocket, so it may e.g. keep a seeded random number
+    /// generator. This is meant for auditing and testing. RFC 6455 requires masking keys to be
+    /// unpredictable, so a custom provider must draw them from a strong source of entropy.
+    pub fn set_masking_key_provider(
+        &mut self,
+        provider: impl FnMut(u64) -> [u8; 4] + Send + Sync + 'static,
+    ) {
+        self.context.set_masking_key_provider(provider);
+    }
+}
+
+impl<R: Read, W: Write> WebSocket<ReadWrite<R, W>> {
+    /// Create a WebSocket from separate reading and writing halves of a transport without
+    /// performing a handshake.
+    ///
+    /// This is the same as [`from_raw_socket`](Self::from_raw_socket) for transports that are
+    /// not a single `Read + Write` stream, e.g. a pair of pipes. The halves can be accessed
+    /// through [`get_ref`](Self::get_ref) and [`get_mut`](Self::get_mut).
+    ///
+    /// # Panics
+    /// Panics if config is invalid e.g. `max_write_buffer_size <= write_buffer_size`.
+    pub fn from_read_write(
+        reader: R,
+        writer: W,
+        role: Role,
+        config: Option<WebSocketConfig>,
+    ) -> Self {
+        Self::from_raw_socket(ReadWrite::new(reader, writer), role, config)
+    }
 }
 
 impl<Stream: Read + Write> WebSocket<Stream> {
@@ -189,6 +519,10 @@ impl<Stream: Read + Write> WebSocket<Stream> {
     /// will be written and flushed on the next call to [`read`](Self::read),
     /// [`write`](Self::write) or [`flush`](Self::flush).
     ///
+    /// Control messages are returned as soon as they arrive, even when they are interleaved
+    /// with the fragments of a data message. The fragments received so far are kept and the
+    /// data message is returned by a later call once it is complete.
+    ///
     /// # Closing the connection
     /// When the remote endpoint decides to close the connection this will return
     /// the close message with an optional close frame.
@@ -196,10 +530,89 @@ impl<Stream: Read + Write> WebSocket<Stream> {
     /// You should continue calling [`read`](Self::read), [`write`](Self::write) or
     /// [`flush`](Self::flush) to drive the reply to the close frame until [`Error::ConnectionClosed`]
     /// is returned. Once that happens it is safe to drop the underlying connection.
+    ///
+    /// # Non-blocking streams
+    /// If the stream returns [`WouldBlock`](io::ErrorKind::WouldBlock), it is passed through as
+    /// [`Error::Io`]. All data read so far is kept, including partial frames and fragments of a
+    /// message, so calling `read` again once the stream is readable resumes where it stopped.
     pub fn read(&mut self) -> Result<Message> {
+        if let Some(err) = self.pending_error.take() {
+            return Err(err);
+        }
         self.context.read(&mut self.socket)
     }
 
+    /// Read the next data message frame by frame instead of reassembling it in memory.
+    ///
+    /// The returned [`MessageReader`] yields the payload as each frame arrives, which allows
+    /// processing very large messages on the fly, e.g. with [`io::copy`]. Control messages
+    /// received before or during the message are handled as with [`read`](Self::read) but are
+    /// not returned. If the peer starts the close handshake instead, the reply is flushed and
+    /// [`Error::ConnectionClosed`] is returned.
+    ///
+    /// [`WebSocketConfig::max_message_size`] and [`WebSocketConfig::max_fragments`] do not
+    /// apply to streamed messages, only [`WebSocketConfig::max_frame_size`] does. If the reader
+    /// is dropped before the end of the message, the remaining frames of the message are
+    /// discarded by subsequent calls to [`read`](Self::read) or `read_stream`.
+    pub fn read_stream(&mut self) -> Result<MessageReader<'_, Stream>> {
+        if let Some(err) = self.pending_error.take() {
+            return Err(err);
+        }
+        MessageReader::new(self)
+    }
+
+    /// Iterate over incoming messages by calling [`read`](Self::read) repeatedly.
+    ///
+    /// The iterator ends once the connection is closed cleanly, i.e. when `read` returns
+    /// [`Error::ConnectionClosed`] or [`Error::AlreadyClosed`]. Any other error is yielded as
+    /// `Some(Err(..))`, so the caller decides whether to keep going.
+    pub fn incoming(&mut self) -> impl Iterator<Item = Result<Message>> + '_ {
+        std::iter::from_fn(move || match self.read() {
+            Err(Error::ConnectionClosed) | Err(Error::AlreadyClosed) => None,
+            result => Some(result),
+        })
+    }
+
+    /// Read up to `max` messages, blocking at most for the first one.
+    ///
+    /// After the first message, only messages that were already received and are buffered
+    /// are returned, the stream is not read from again. Control messages are handled and
+    /// returned as with [`read`](Self::read). The batch ends after a close message.
+    ///
+    /// If an error occurs after some messages were read, those messages are returned and the
+    /// error is returned by the next call to `read_batch`, [`read`](Self::read) or
+    /// [`read_stream`](Self::read_stream).
+    pub fn read_batch(&mut self, max: usize) -> Result<Vec<Message>> {
+        let mut messages = Vec::new();
+        while messages.len() < max {
+            let result = if messages.is_empty() {
+                self.read()
+            } else {
+                self.context.read(&mut Buffered(&mut self.socket))
+            };
+            match result {
+                Ok(message) => {
+                    let close = message.is_close();
+                    messages.push(message);
+                    if close {
+                        break;
+                    }
+                }
+                Err(Error::Io(err))
+                    if !messages.is_empty() && err.kind() == io::ErrorKind::WouldBlock =>
+                {
+                    break
+                }
+                Err(err) if messages.is_empty() => return Err(err),
+                Err(err) => {
+                    self.pending_error = Some(err);
+                    break;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
     /// Writes and immediately flushes a message.
     /// Equivalent to calling [`write`](Self::write) then [`flush`](Self::flush).
     pub fn send(&mut self, message: Message) -> Result<()> {
@@ -245,6 +658,24 @@ impl<Stream: Read + Write> WebSocket<Stream> {
         self.context.write(&mut self.socket, message)
     }
 
+    /// Send a message by reference and ensure the message is flushed.
+    ///
+    /// Equivalent to calling [`write_ref`](Self::write_ref) then [`flush`](Self::flush).
+    pub fn send_ref(&mut self, message: &Message) -> Result<()> {
+        self.write_ref(message)?;
+        self.flush()
+    }
+
+    /// Write a message to the provided stream without taking ownership of it.
+    ///
+    /// This behaves exactly like [`write`](Self::write) but encodes the frame straight from
+    /// the borrowed payload, so the same message can be written to many sockets without
+    /// cloning it for each one. Pong and close messages are still cloned, as these are
+    /// queued until they can be sent.
+    pub fn write_ref(&mut self, message: &Message) -> Result<()> {
+        self.context.write_ref(&mut self.socket, message)
+    }
+
     /// Flush writes.
     ///
     /// Ensures all messages previously passed to [`write`](Self::write) and automatic
@@ -253,12 +684,45 @@ impl<Stream: Read + Write> WebSocket<Stream> {
         self.context.flush(&mut self.socket)
     }
 
+    /// Flush automatically queued pong and close replies, if there are any.
+    ///
+    /// Does nothing if no reply is pending, leaving messages passed to [`write`](Self::write)
+    /// buffered. Frames are never reordered though: a reply is queued after the messages that
+    /// were already written, so those messages are flushed together with it.
+    pub fn flush_control(&mut self) -> Result<()> {
+        self.context.flush_control(&mut self.socket)
+    }
+
+    /// Close the connection if no frame was received for
+    /// [`WebSocketConfig::idle_timeout`] as of `now`.
+    ///
+    /// This also enforces [`WebSocketConfig::close_timeout`]: once it has elapsed,
+    /// [`Error::ConnectionClosed`] is returned and the underlying connection may be dropped.
+    ///
+    /// This needs to be called periodically, e.g. whenever [`read`](Self::read) returns because
+    /// of a read timeout on the stream. The connection is closed with code 1001 like
+    /// [`close`](Self::close) would, so [`read`](Self::read) should be called afterwards to
+    /// complete the close handshake. Does nothing if no idle timeout is configured or the
+    /// connection is already closing.
+    pub fn tick(&mut self, now: Instant) -> Result<()> {
+        self.context.tick(&mut self.socket, now)
+    }
+
     /// Close the connection.
     ///
     /// This function guarantees that the close frame will be queued.
     /// There is no need to call it again. Calling this function is
     /// the same as calling `write(Message::Close(..))`.
     ///
+    /// Returns [`ProtocolError::InvalidCloseCode`] without closing if the code is not allowed
+    /// in a close frame (see [`CloseCode::is_allowed`]).
+    ///
+    /// Calling it again is harmless, no further close frame is sent. Later calls only
+    /// [`flush`](Self::flush), so they return the same errors `flush` would.
+    ///
+    /// If [`WebSocketConfig::auto_close`] is disabled, this is also how a close frame received
+    /// from the peer is answered.
+    ///
     /// After queuing the close frame you should continue calling [`read`](Self::read) or
     /// [`flush`](Self::flush) to drive the close handshake to completion.
     ///
@@ -297,6 +761,30 @@ impl<Stream: Read + Write> WebSocket<Stream> {
     }
 }
 
+impl<Stream: Read + Write + ShutdownWrite> WebSocket<Stream> {
+    /// Close the connection without waiting for the close handshake to complete.
+    ///
+    /// Queues and flushes a close frame like [`close`](Self::close), then shuts down the writing
+    /// half of the underlying stream. As opposed to `close`, the reply of the peer is not
+    /// awaited, so this is meant for giving up on a connection, e.g. after an error.
+    ///
+    /// The WebSocket is terminated afterwards even if sending the close frame failed, and any
+    /// further calls return [`Error::AlreadyClosed`]. An error sending the close frame is returned
+    /// after the stream has been shut down.
+    ///
+    /// If the close code is not allowed, [`ProtocolError::InvalidCloseCode`] is returned and
+    /// nothing is sent or shut down, like with `close`.
+    pub fn close_immediately(&mut self, code: Option<CloseFrame>) -> Result<()> {
+        let sent = self.close(code);
+        if let Err(Error::Protocol(ProtocolError::InvalidCloseCode(_))) = sent {
+            return sent;
+        }
+        self.context.state = WebSocketState::Terminated;
+        self.socket.shutdown_write()?;
+        sent
+    }
+}
+
 /// A context for managing WebSocket stream.
 #[derive(Debug)]
 pub struct WebSocketContext {
@@ -313,6 +801,22 @@ pub struct WebSocketContext {
     /// True indicates there is an additional message (like a pong)
     /// that failed to flush previously and we should try again.
     unflushed_additional: bool,
+    /// The moment our close frame was queued, used to enforce the close timeout.
+    close_sent_at: Option<Instant>,
+    /// The peer's close frame is waiting to be answered by the application.
+    close_reply_pending: bool,
+    /// The moment the last frame was received, used to enforce the idle timeout.
+    last_received: Instant,
+    /// Number of frames masked so far.
+    masked_frames: u64,
+    /// Receive: data frames are returned as they are instead of being reassembled.
+    pass_fragments: bool,
+    /// Receive: a message returned frame by frame has not been completed yet.
+    streaming: bool,
+    /// Called on control frame activity.
+    on_control_event: Option<Hook<OnControlEvent>>,
+    /// Provides the masking keys of a client instead of generating random ones.
+    masking_key_provider: Option<Hook<MaskingKeyProvider>>,
     /// The configuration for the websocket session.
     config: WebSocketConfig,
 }
@@ -345,6 +849,14 @@ impl WebSocketContext {
             incomplete: None,
             additional_send: None,
             unflushed_additional: false,
+            close_sent_at: None,
+            close_reply_pending: false,
+            last_received: Instant::now(),
+            masked_frames: 0,
+            pass_fragments: false,
+            streaming: false,
+            on_control_event: None,
+            masking_key_provider: None,
             config,
         }
     }
@@ -365,6 +877,31 @@ impl WebSocketContext {
         &self.config
     }
 
+    /// Get the role of this endpoint.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Set a function called on control frame activity.
+    ///
+    /// See [`WebSocket::set_on_control_event`].
+    pub fn set_on_control_event(
+        &mut self,
+        callback: impl FnMut(ControlEvent<'_>) + Send + Sync + 'static,
+    ) {
+        self.on_control_event = Some(Hook(Box::new(callback)));
+    }
+
+    /// Set a function providing the masking keys of a client.
+    ///
+    /// See [`WebSocket::set_masking_key_provider`].
+    pub fn set_masking_key_provider(
+        &mut self,
+        provider: impl FnMut(u64) -> [u8; 4] + Send + Sync + 'static,
+    ) {
+        self.masking_key_provider = Some(Hook(Box::new(provider)));
+    }
+
     /// Check if it is possible to read messages.
     ///
     /// Reading is impossible after receiving `Message::Close`. It is still possible after
@@ -380,6 +917,14 @@ impl WebSocketContext {
         self.state.is_active()
     }
 
+    /// Check if there is data that is not written and flushed to the stream yet.
+    pub fn has_pending_writes(&self) -> bool {
+        self.frame.out_buffer_len() > 0
+            || self.frame.needs_flush()
+            || self.additional_send.is_some()
+            || self.unflushed_additional
+    }
+
     /// Read a message from the provided stream, if possible.
     ///
     /// This function sends pong and close responses automatically.
@@ -391,6 +936,12 @@ impl WebSocketContext {
         // Do not read from already closed connections.
         self.state.check_not_terminated()?;
 
+        if self.close_timed_out(Instant::now()) {
+            debug!("Peer did not complete the close handshake in time");
+            self.state = WebSocketState::Terminated;
+            return Err(Error::ConnectionClosed);
+        }
+
         loop {
             if self.additional_send.is_some() || self.unflushed_additional {
                 // Since we may get ping or close, we need to reply to the messages even during read.
@@ -402,20 +953,47 @@ impl WebSocketContext {
                     }
                     Err(err) => return Err(err),
                 }
-            } else if self.role == Role::Server && !self.state.can_read() {
+            } else if self.role == Role::Server
+                && !self.state.can_read()
+                && !self.close_reply_pending
+            {
                 self.state = WebSocketState::Terminated;
                 return Err(Error::ConnectionClosed);
             }
 
             // If we get here, either write blocks or we have nothing to write.
             // Thus if read blocks, just let it return WouldBlock.
-            if let Some(message) = self.read_message_frame(stream)? {
-                trace!("Received message {message}");
-                return Ok(message);
+            match self.read_message_frame(stream) {
+                Ok(Some(message)) => {
+                    trace!("Received message {message}");
+                    if self.config.skip_empty_messages
+                        && (message.is_text() || message.is_binary())
+                        && message.is_empty()
+                    {
+                        continue;
+                    }
+                    return Ok(message);
+                }
+                Ok(None) => {}
+                Err(err) => return Err(self.fail(stream, err)),
             }
         }
     }
 
+    /// Read the next fragment of a data message, see [`WebSocket::read_stream`].
+    ///
+    /// Data frames are returned as [`Message::Frame`], unless they complete a message that
+    /// was already partially read by [`read`](Self::read).
+    fn read_fragment<Stream>(&mut self, stream: &mut Stream) -> Result<Message>
+    where
+        Stream: Read + Write,
+    {
+        self.pass_fragments = true;
+        let result = self.read(stream);
+        self.pass_fragments = false;
+        result
+    }
+
     /// Write a message to the provided stream.
     ///
     /// A subsequent call should be made to [`flush`](Self::flush) to flush writes.
@@ -433,8 +1011,9 @@ impl WebSocketContext {
         // When terminated, return AlreadyClosed.
         self.state.check_not_terminated()?;
 
-        // Do not write after sending a close frame.
-        if !self.state.is_active() {
+        // Do not write after sending a close frame, except for answering the peer's close.
+        let close_reply = self.close_reply_pending && matches!(message, Message::Close(_));
+        if !self.state.is_active() && !close_reply {
             return Err(Error::Protocol(ProtocolError::SendAfterClosing));
         }
 
@@ -458,6 +1037,40 @@ impl WebSocketContext {
         Ok(())
     }
 
+    /// Write a message to the provided stream without taking ownership of it.
+    ///
+    /// See [`WebSocket::write_ref`].
+    pub fn write_ref<Stream>(&mut self, stream: &mut Stream, message: &Message) -> Result<()>
+    where
+        Stream: Read + Write,
+    {
+        // When terminated, return AlreadyClosed.
+        self.state.check_not_terminated()?;
+
+        // Do not write after sending a close frame, except for answering the peer's close.
+        let close_reply = self.close_reply_pending && matches!(message, Message::Close(_));
+        if !self.state.is_active() && !close_reply {
+            return Err(Error::Protocol(ProtocolError::SendAfterClosing));
+        }
+
+        let header = |opcode| FrameHeader { is_final: true, opcode, ..FrameHeader::default() };
+        let (mut header, payload) = match message {
+            Message::Text(data) => (header(OpCode::Data(OpData::Text)), data.as_bytes()),
+            Message::Binary(data) => (header(OpCode::Data(OpData::Binary)), data.as_slice()),
+            Message::Ping(data) => (header(OpCode::Control(OpCtl::Ping)), data.as_slice()),
+            Message::Pong(_) | Message::Close(_) => return self.write(stream, message.clone()),
+            Message::Frame(f) => (f.header().clone(), f.payload().as_slice()),
+        };
+        self.mask_header(&mut header);
+
+        self.frame.buffer_frame_ref(stream, &header, payload).check_connection_reset(self.state)?;
+        let should_flush = self._write(stream, None)?;
+        if should_flush {
+            self.flush(stream)?;
+        }
+        Ok(())
+    }
+
     /// Flush writes.
     ///
     /// Ensures all messages previously passed to [`write`](Self::write) and automatically
@@ -468,12 +1081,25 @@ impl WebSocketContext {
         Stream: Read + Write,
     {
         self._write(stream, None)?;
-        self.frame.write_out_buffer(stream)?;
-        stream.flush()?;
+        self.frame.flush(stream)?;
         self.unflushed_additional = false;
         Ok(())
     }
 
+    /// Flush automatically queued pong and close replies, if there are any.
+    ///
+    /// See [`WebSocket::flush_control`].
+    pub fn flush_control<Stream>(&mut self, stream: &mut Stream) -> Result<()>
+    where
+        Stream: Read + Write,
+    {
+        if self.additional_send.is_some() || self.unflushed_additional {
+            self.flush(stream)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Writes any data in the out_buffer, `additional_send` and given `data`.
     ///
     /// Does **not** flush.
@@ -492,6 +1118,12 @@ impl WebSocketContext {
         // respond with Pong frame as soon as is practical. (RFC 6455)
         let should_flush = if let Some(msg) = self.additional_send.take() {
             trace!("Sending pong/close");
+            let pong = match self.on_control_event {
+                Some(_) if msg.header().opcode == OpCode::Control(OpCtl::Pong) => {
+                    Some(msg.payload().to_vec())
+                }
+                _ => None,
+            };
             match self.buffer_frame(stream, msg) {
                 Err(Error::WriteBufferFull(Message::Frame(msg))) => {
                     // if an system message would exceed the buffer put it back in
@@ -501,14 +1133,19 @@ impl WebSocketContext {
                     false
                 }
                 Err(err) => return Err(err),
-                Ok(_) => true,
+                Ok(_) => {
+                    if let Some(data) = pong {
+                        self.emit(ControlEvent::AutoPongSent(&data));
+                    }
+                    true
+                }
             }
         } else {
             self.unflushed_additional
         };
 
         // If we're closing and there is nothing to send anymore, we should close the connection.
-        if self.role == Role::Server && !self.state.can_read() {
+        if self.role == Role::Server && !self.state.can_read() && !self.close_reply_pending {
             // The underlying TCP connection, in most normal cases, SHOULD be closed
             // first by the server, so that it holds the TIME_WAIT state and not the
             // client (as this would prevent it from re-opening the connection for 2
@@ -528,18 +1165,58 @@ impl WebSocketContext {
     /// This function guarantees that the close frame will be queued.
     /// There is no need to call it again. Calling this function is
     /// the same as calling `send(Message::Close(..))`.
+    ///
+    /// Returns [`ProtocolError::InvalidCloseCode`] without closing if the code must not be sent,
+    /// i.e. the same codes that are rejected when received (see [`CloseCode::is_allowed`]).
     pub fn close<Stream>(&mut self, stream: &mut Stream, code: Option<CloseFrame>) -> Result<()>
     where
         Stream: Read + Write,
     {
+        if let Some(CloseFrame { code, .. }) = code {
+            if !code.is_allowed() {
+                return Err(Error::Protocol(ProtocolError::InvalidCloseCode(code)));
+            }
+        }
         if let WebSocketState::Active = self.state {
             self.state = WebSocketState::ClosedByUs;
+            self.close_sent_at = Some(Instant::now());
             let frame = Frame::close(code);
             self._write(stream, Some(frame))?;
+        } else if self.close_reply_pending {
+            self.close_reply_pending = false;
+            self.close_sent_at = Some(Instant::now());
+            self._write(stream, Some(Frame::close(code)))?;
         }
         self.flush(stream)
     }
 
+    /// Close the connection if it has been idle for too long, or terminate it if the peer did
+    /// not complete the close handshake in time.
+    ///
+    /// See [`WebSocket::tick`].
+    pub fn tick<Stream>(&mut self, stream: &mut Stream, now: Instant) -> Result<()>
+    where
+        Stream: Read + Write,
+    {
+        if self.state != WebSocketState::Terminated && self.close_timed_out(now) {
+            debug!("Peer did not complete the close handshake in time");
+            self.state = WebSocketState::Terminated;
+            return Err(Error::ConnectionClosed);
+        }
+
+        match self.config.idle_timeout {
+            Some(timeout)
+                if self.state.is_active()
+                    && now.saturating_duration_since(self.last_received) >= timeout =>
+            {
+                debug!("No frame received for {timeout:?}, closing the connection");
+                let frame = CloseFrame { code: CloseCode::Away, reason: "Idle timeout".into() };
+                self.close(stream, Some(frame))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Try to decode one message frame. May return None.
     fn read_message_frame<Stream>(&mut self, stream: &mut Stream) -> Result<Option<Message>>
     where
@@ -550,6 +1227,7 @@ impl WebSocketContext {
             .read_frame(stream, self.config.max_frame_size)
             .check_connection_reset(self.state)?
         {
+            self.last_received = Instant::now();
             if !self.state.can_read() {
                 return Err(Error::Protocol(ProtocolError::ReceivedAfterClosing));
             }
@@ -595,30 +1273,48 @@ impl WebSocketContext {
                         _ if !frame.header().is_final => {
                             Err(Error::Protocol(ProtocolError::FragmentedControlFrame))
                         }
-                        _ if frame.payload().len() > 125 => {
-                            Err(Error::Protocol(ProtocolError::ControlFrameTooBig))
+                        OpCtl::Close => {
+                            let close = frame.into_close()?;
+                            self.emit(ControlEvent::CloseReceived(close.as_ref()));
+                            Ok(self.do_close(close)?.map(Message::Close))
                         }
-                        OpCtl::Close => Ok(self.do_close(frame.into_close()?).map(Message::Close)),
                         OpCtl::Reserved(i) => {
                             Err(Error::Protocol(ProtocolError::UnknownControlFrameType(i)))
                         }
                         OpCtl::Ping => {
                             let data = frame.into_data();
+                            self.emit(ControlEvent::PingReceived(&data));
                             // No ping processing after we sent a close frame.
                             if self.state.is_active() {
                                 self.set_additional(Frame::pong(data.clone()));
                             }
                             Ok(Some(Message::Ping(data)))
                         }
-                        OpCtl::Pong => Ok(Some(Message::Pong(frame.into_data()))),
+                        OpCtl::Pong => {
+                            let data = frame.into_data();
+                            self.emit(ControlEvent::PongReceived(&data));
+                            Ok(Some(Message::Pong(data)))
+                        }
                     }
                 }
 
                 OpCode::Data(data) => {
                     let fin = frame.header().is_final;
                     match data {
+                        OpData::Continue if self.streaming => {
+                            self.streaming = !fin;
+                            // Drop the rest of a message if its reader went away.
+                            Ok(Some(Message::Frame(frame)).filter(|_| self.pass_fragments))
+                        }
                         OpData::Continue => {
                             if let Some(ref mut msg) = self.incomplete {
+                                if let Some(max_fragments) = self.config.max_fragments {
+                                    if msg.fragments() >= max_fragments {
+                                        return Err(Error::Capacity(
+                                            CapacityError::TooManyFragments { max_fragments },
+                                        ));
+                                    }
+                                }
                                 msg.extend(frame.into_data(), self.config.max_message_size)?;
                             } else {
                                 return Err(Error::Protocol(
@@ -631,9 +1327,13 @@ impl WebSocketContext {
                                 Ok(None)
                             }
                         }
-                        c if self.incomplete.is_some() => {
+                        c if self.incomplete.is_some() || self.streaming => {
                             Err(Error::Protocol(ProtocolError::ExpectedFragment(c)))
                         }
+                        OpData::Text | OpData::Binary if self.pass_fragments => {
+                            self.streaming = !fin;
+                            Ok(Some(Message::Frame(frame)))
+                        }
                         OpData::Text | OpData::Binary => {
                             let msg = {
                                 let message_type = match data {
@@ -670,41 +1370,81 @@ impl WebSocketContext {
     }
 
     /// Received a close frame. Tells if we need to return a close frame to the user.
+    ///
+    /// A close frame with a code that is not allowed is answered with a protocol error close
+    /// frame and reported as [`ProtocolError::InvalidCloseCode`].
     #[allow(clippy::option_option)]
-    fn do_close<'t>(&mut self, close: Option<CloseFrame<'t>>) -> Option<Option<CloseFrame<'t>>> {
+    fn do_close<'t>(
+        &mut self,
+        close: Option<CloseFrame<'t>>,
+    ) -> Result<Option<Option<CloseFrame<'t>>>> {
         debug!("Received close frame: {close:?}");
+        let invalid_code = close.as_ref().map(|frame| frame.code).filter(|code| !code.is_allowed());
         match self.state {
             WebSocketState::Active => {
                 self.state = WebSocketState::ClosedByPeer;
 
-                let close = close.map(|frame| {
-                    if !frame.code.is_allowed() {
-                        CloseFrame {
-                            code: CloseCode::Protocol,
-                            reason: "Protocol violation".into(),
-                        }
-                    } else {
-                        frame
+                let reply = match invalid_code {
+                    Some(_) => Frame::close(Some(CloseFrame {
+                        code: CloseCode::Protocol,
+                        reason: "Protocol violation".into(),
+                    })),
+                    None if !self.config.auto_close => {
+                        debug!("Leaving the reply to close to the application");
+                        self.close_reply_pending = true;
+                        return Ok(Some(close));
                     }
-                });
-
-                let reply = Frame::close(close.clone());
+                    None => Frame::close(close.clone()),
+                };
                 debug!("Replying to close with {reply:?}");
                 self.set_additional(reply);
-
-                Some(close)
+                self.close_sent_at = Some(Instant::now());
             }
             WebSocketState::ClosedByPeer | WebSocketState::CloseAcknowledged => {
                 // It is already closed, just ignore.
-                None
+                return Ok(None);
             }
             WebSocketState::ClosedByUs => {
                 // We received a reply.
                 self.state = WebSocketState::CloseAcknowledged;
-                Some(close)
             }
             WebSocketState::Terminated => unreachable!(),
         }
+
+        match invalid_code {
+            Some(code) => Err(Error::Protocol(ProtocolError::InvalidCloseCode(code))),
+            None => Ok(Some(close)),
+        }
+    }
+
+    /// Send a close frame describing a read error if configured to, see
+    /// [`WebSocketConfig::close_on_error`].
+    fn fail<Stream>(&mut self, stream: &mut Stream, err: Error) -> Error
+    where
+        Stream: Read + Write,
+    {
+        let (code, reason) = match err {
+            // There is nobody left to tell.
+            Error::Protocol(ProtocolError::ResetWithoutClosingHandshake) => return err,
+            Error::Protocol(_) => (CloseCode::Protocol, "Protocol violation"),
+            Error::Utf8 => (CloseCode::Invalid, "Invalid UTF-8"),
+            Error::Capacity(_) => (CloseCode::Size, "Message too big"),
+            _ => return err,
+        };
+        if self.config.close_on_error && self.state.is_active() {
+            debug!("Failing the connection with {code}: {err}");
+            // Best effort, the read error is what matters to the caller.
+            let _ = self.close(stream, Some(CloseFrame { code, reason: reason.into() }));
+        }
+        err
+    }
+
+    /// Check if the peer failed to complete the close handshake within the configured timeout.
+    fn close_timed_out(&self, now: Instant) -> bool {
+        match (self.config.close_timeout, self.close_sent_at) {
+            (Some(timeout), Some(sent_at)) => now.saturating_duration_since(sent_at) >= timeout,
+            _ => false,
+        }
     }
 
     /// Write a single frame into the write-buffer.
@@ -712,17 +1452,33 @@ impl WebSocketContext {
     where
         Stream: Read + Write,
     {
+        self.mask_header(frame.header_mut());
+
+        trace!("Sending frame: {frame:?}");
+        self.frame.buffer_frame(stream, frame).check_connection_reset(self.state)
+    }
+
+    /// Set the mask of an outgoing frame if this endpoint is a client.
+    fn mask_header(&mut self, header: &mut FrameHeader) {
         match self.role {
             Role::Server => {}
             Role::Client => {
                 // 5.  If the data is being sent by the client, the frame(s) MUST be
                 // masked as defined in Section 5.3. (RFC 6455)
-                frame.set_random_mask();
+                match &mut self.masking_key_provider {
+                    Some(Hook(provider)) => header.mask = Some(provider(self.masked_frames)),
+                    None => header.set_random_mask(),
+                }
+                self.masked_frames = self.masked_frames.wrapping_add(1);
             }
         }
+    }
 
-        trace!("Sending frame: {frame:?}");
-        self.frame.buffer_frame(stream, frame).check_connection_reset(self.state)
+    /// Report a control frame event to the callback, if any.
+    fn emit(&mut self, event: ControlEvent<'_>) {
+        if let Some(Hook(callback)) = &mut self.on_control_event {
+            callback(event);
+        }
     }
 
     /// Replace `additional_send` if it is currently a `Pong` message.
@@ -737,6 +1493,25 @@ impl WebSocketContext {
     }
 }
 
+/// A stream that only passes writes through, reads block as if no more data was received.
+struct Buffered<'a, Stream>(&'a mut Stream);
+
+impl<Stream> Read for Buffered<'_, Stream> {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+impl<Stream: Write> Write for Buffered<'_, Stream> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
 /// The current connection state.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum WebSocketState {
@@ -796,10 +1571,21 @@ impl<T> CheckConnectionReset for Result<T> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Message, Role, WebSocket, WebSocketConfig};
-    use crate::error::{CapacityError, Error};
-
-    use std::{io, io::Cursor};
+    use super::{
+        CloseCode, CloseFrame, Frame, Message, OpCode, OpData, Role, WebSocket, WebSocketConfig,
+    };
+    use crate::{
+        error::{CapacityError, Error, ProtocolError},
+        protocol::frame::FrameSocket,
+        stream::ShutdownWrite,
+    };
+
+    use std::{
+        io,
+        io::Cursor,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
 
     struct WriteMoc<Stream>(Stream);
 
@@ -832,6 +1618,528 @@ mod tests {
         assert_eq!(socket.read().unwrap(), Message::Binary(vec![0x01, 0x02, 0x03]));
     }
 
+    #[test]
+    fn incoming_until_closed() {
+        let incoming = Cursor::new(vec![0x81, 0x02, 0x48, 0x69, 0x8a, 0x01, 0x03, 0x88, 0x00]);
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, None);
+        let messages: Vec<Message> = socket.incoming().map(Result::unwrap).collect();
+        assert_eq!(
+            messages,
+            vec![Message::Text("Hi".into()), Message::Pong(vec![3]), Message::Close(None)]
+        );
+        assert!(socket.incoming().next().is_none());
+    }
+
+    #[test]
+    fn receive_interrupted() {
+        // Hands out at most 3 bytes per call and blocks in between.
+        struct Trickle {
+            data: Cursor<Vec<u8>>,
+            blocked: bool,
+        }
+
+        impl io::Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.blocked = !self.blocked;
+                if self.blocked {
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, "try again"));
+                }
+                let len = buf.len().min(3);
+                self.data.read(&mut buf[..len])
+            }
+        }
+
+        // Fragmented "Hello, World!" with a ping in between.
+        let data = Cursor::new(vec![
+            0x01, 0x07, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x89, 0x02, 0x01, 0x02, 0x80,
+            0x06, 0x57, 0x6f, 0x72, 0x6c, 0x64, 0x21,
+        ]);
+        let mut socket = WebSocket::from_raw_socket(
+            WriteMoc(Trickle { data, blocked: false }),
+            Role::Client,
+            None,
+        );
+
+        let mut read = || loop {
+            match socket.read() {
+                Err(Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                other => break other.unwrap(),
+            }
+        };
+        assert_eq!(read(), Message::Ping(vec![1, 2]));
+        assert_eq!(read(), Message::Text("Hello, World!".into()));
+    }
+
+    #[test]
+    fn into_inner() {
+        let socket = WebSocket::from_partially_read(
+            WriteMoc(Cursor::new(vec![0x03])),
+            vec![0x82, 0x03, 0x01, 0x02],
+            Role::Client,
+            None,
+        );
+        let (_, rest) = socket.into_inner();
+        assert_eq!(rest, vec![0x82, 0x03, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn into_inner_partial_frame() {
+        struct Blocked;
+
+        impl io::Read for Blocked {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "nothing to read"))
+            }
+        }
+
+        // The header of the second frame is parsed while waiting for the rest of its payload.
+        let incoming = io::Read::chain(
+            Cursor::new(vec![0x81, 0x01, b'a', 0x82, 0x7e, 0x00, 0x80, 0x01, 0x02]),
+            Blocked,
+        );
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        assert_eq!(socket.read().unwrap(), Message::Text("a".into()));
+        assert!(
+            matches!(socket.read(), Err(Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock)
+        );
+        let (_, rest) = socket.into_inner();
+        assert_eq!(rest, vec![0x82, 0x7e, 0x00, 0x80, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn pending_writes() {
+        let mut socket =
+            WebSocket::from_raw_socket(WriteMoc(Cursor::new(vec![])), Role::Client, None);
+        assert!(!socket.has_pending_writes());
+        socket.write(Message::Text("Hello".into())).unwrap();
+        assert!(socket.has_pending_writes());
+        socket.flush().unwrap();
+        assert!(!socket.has_pending_writes());
+
+        // Written to the stream once the write buffer is full, but not flushed yet.
+        let config = WebSocketConfig { write_buffer_size: 0, ..WebSocketConfig::default() };
+        let mut socket =
+            WebSocket::from_read_write(io::empty(), Vec::new(), Role::Server, Some(config));
+        socket.write(Message::Text("Hello".into())).unwrap();
+        assert_eq!(socket.get_ref().writer().len(), 7);
+        assert!(socket.has_pending_writes());
+        socket.flush().unwrap();
+        assert!(!socket.has_pending_writes());
+
+        // An automatic pong reply is pending until flushed.
+        let incoming = Cursor::new(vec![0x89, 0x01, 0x01]);
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, None);
+        assert_eq!(socket.read().unwrap(), Message::Ping(vec![1]));
+        assert!(socket.has_pending_writes());
+        socket.flush().unwrap();
+        assert!(!socket.has_pending_writes());
+    }
+
+    #[test]
+    fn close_immediately() {
+        #[derive(Default)]
+        struct ShutdownMoc {
+            output: Vec<u8>,
+            shut_down: bool,
+        }
+
+        impl io::Read for ShutdownMoc {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "nothing to read"))
+            }
+        }
+
+        impl io::Write for ShutdownMoc {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.output.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl ShutdownWrite for ShutdownMoc {
+            fn shutdown_write(&mut self) -> io::Result<()> {
+                self.shut_down = true;
+                Ok(())
+            }
+        }
+
+        let mut socket = WebSocket::from_raw_socket(ShutdownMoc::default(), Role::Server, None);
+        // An invalid close code leaves the connection as it is.
+        assert!(matches!(
+            socket.close_immediately(Some(CloseFrame {
+                code: CloseCode::Abnormal,
+                reason: "".into()
+            })),
+            Err(Error::Protocol(ProtocolError::InvalidCloseCode(CloseCode::Abnormal)))
+        ));
+        assert!(socket.get_ref().output.is_empty());
+        assert!(!socket.get_ref().shut_down);
+        assert!(socket.can_write());
+
+        socket
+            .close_immediately(Some(CloseFrame { code: CloseCode::Normal, reason: "".into() }))
+            .unwrap();
+        assert_eq!(socket.get_ref().output, vec![0x88, 0x02, 0x03, 0xe8]);
+        assert!(socket.get_ref().shut_down);
+        assert!(matches!(socket.read(), Err(Error::AlreadyClosed)));
+    }
+
+    #[test]
+    fn control_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let incoming = Cursor::new(vec![0x89, 0x01, 0x01, 0x8a, 0x01, 0x02, 0x88, 0x00]);
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, None);
+        let recorded = Arc::clone(&events);
+        socket.set_on_control_event(move |event| {
+            recorded.lock().unwrap().push(format!("{event:?}"));
+        });
+        assert_eq!(socket.read().unwrap(), Message::Ping(vec![1]));
+        assert_eq!(socket.read().unwrap(), Message::Pong(vec![2]));
+        assert_eq!(socket.read().unwrap(), Message::Close(None));
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "PingReceived([1])",
+                "AutoPongSent([1])",
+                "PongReceived([2])",
+                "CloseReceived(None)"
+            ]
+        );
+    }
+
+    #[test]
+    fn read_write_halves() {
+        let reader = Cursor::new(vec![0x81, 0x02, 0x48, 0x69]);
+        let mut socket = WebSocket::from_read_write(reader, Vec::new(), Role::Client, None);
+        assert_eq!(socket.read().unwrap(), Message::Text("Hi".into()));
+        socket.send(Message::Text("Hello".into())).unwrap();
+        // Masked text frame: header, masking key and payload.
+        let written = socket.get_ref().writer();
+        assert_eq!(written.len(), 2 + 4 + 5);
+        assert_eq!(written[..2], [0x81, 0x85]);
+    }
+
+    #[test]
+    fn unmasked_continuation_from_client() {
+        // Masked (with a zero key) first fragment followed by an unmasked continuation.
+        let incoming =
+            Cursor::new(vec![0x01, 0x82, 0x00, 0x00, 0x00, 0x00, 0x48, 0x69, 0x80, 0x01, 0x21]);
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Server, None);
+        assert!(matches!(
+            socket.read(),
+            Err(Error::Protocol(ProtocolError::UnmaskedFrameFromClient))
+        ));
+    }
+
+    #[test]
+    fn flush_control() {
+        // Masked (with a zero key) ping from the client.
+        let reader = Cursor::new(vec![0x89, 0x81, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let mut socket = WebSocket::from_read_write(reader, Vec::new(), Role::Server, None);
+
+        socket.write(Message::Text("Hi".into())).unwrap();
+        socket.flush_control().unwrap();
+        assert!(socket.get_ref().writer().is_empty());
+
+        assert_eq!(socket.read().unwrap(), Message::Ping(vec![1]));
+        socket.flush_control().unwrap();
+        assert_eq!(socket.get_ref().writer(), &[0x81, 0x02, 0x48, 0x69, 0x8a, 0x01, 0x01]);
+        assert!(!socket.has_pending_writes());
+    }
+
+    #[test]
+    fn masking_key_provider() {
+        let mut socket = WebSocket::from_read_write(io::empty(), Vec::new(), Role::Client, None);
+        // The provider may keep state between frames.
+        let mut key = [0; 4];
+        socket.set_masking_key_provider(move |_| {
+            let current = key;
+            key = key.map(|b| b + 1);
+            current
+        });
+        socket.send(Message::Text("Hi".into())).unwrap();
+        socket.send(Message::Text("Hi".into())).unwrap();
+        assert_eq!(
+            socket.get_ref().writer(),
+            &[
+                0x81, 0x82, 0x00, 0x00, 0x00, 0x00, 0x48, 0x69, // first key
+                0x81, 0x82, 0x01, 0x01, 0x01, 0x01, 0x49, 0x68, // second key
+            ]
+        );
+    }
+
+    #[test]
+    fn write_ref() {
+        let messages = [
+            Message::Text("Hello".into()),
+            Message::Binary(vec![0; 300]),
+            Message::Ping(vec![1, 2, 3]),
+            Message::Pong(vec![4]),
+            Message::Frame(Frame::message(vec![5, 6], OpCode::Data(OpData::Binary), false)),
+            Message::Close(None),
+        ];
+        for role in [Role::Server, Role::Client] {
+            let mut owned = WebSocket::from_read_write(io::empty(), Vec::new(), role, None);
+            let mut borrowed = WebSocket::from_read_write(io::empty(), Vec::new(), role, None);
+            owned.set_masking_key_provider(|n| [n as u8, 1, 2, 3]);
+            borrowed.set_masking_key_provider(|n| [n as u8, 1, 2, 3]);
+            for message in &messages {
+                owned.send(message.clone()).unwrap();
+                borrowed.send_ref(message).unwrap();
+            }
+            assert_eq!(borrowed.get_ref().writer(), owned.get_ref().writer());
+            assert!(matches!(
+                borrowed.send_ref(&messages[0]),
+                Err(Error::Protocol(ProtocolError::SendAfterClosing))
+            ));
+        }
+    }
+
+    #[test]
+    fn role() {
+        for role in [Role::Server, Role::Client] {
+            let socket = WebSocket::from_raw_socket(WriteMoc(io::empty()), role, None);
+            assert_eq!(socket.role(), role);
+        }
+    }
+
+    #[test]
+    fn receive_after_close() {
+        // Close frame followed by a text frame "Hi".
+        let incoming = Cursor::new(vec![0x88, 0x00, 0x81, 0x02, 0x48, 0x69]);
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, None);
+        assert_eq!(socket.read().unwrap(), Message::Close(None));
+        assert!(matches!(socket.read(), Err(Error::Protocol(ProtocolError::ReceivedAfterClosing))));
+    }
+
+    #[test]
+    fn receive_reserved_close_code() {
+        for code in [1005u16, 1006, 1015] {
+            let mut incoming = vec![0x88, 0x02];
+            incoming.extend(code.to_be_bytes());
+            let mut socket =
+                WebSocket::from_raw_socket(WriteMoc(Cursor::new(incoming)), Role::Client, None);
+            assert!(matches!(
+                socket.read(),
+                Err(Error::Protocol(ProtocolError::InvalidCloseCode(c))) if u16::from(c) == code
+            ));
+            assert!(!socket.can_write());
+        }
+
+        // A close frame without a status code is reported as such.
+        let incoming = Cursor::new(vec![0x88, 0x00]);
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, None);
+        assert_eq!(socket.read().unwrap(), Message::Close(None));
+    }
+
+    #[test]
+    fn invalid_utf8_close() {
+        let incoming = Cursor::new(vec![0x81, 0x02, 0xff, 0xfe]);
+        let config = WebSocketConfig { close_on_error: true, ..WebSocketConfig::default() };
+        let mut socket =
+            WebSocket::from_read_write(incoming, Vec::new(), Role::Client, Some(config));
+        socket.set_masking_key_provider(|_| [0; 4]);
+        assert!(matches!(socket.read(), Err(Error::Utf8)));
+        assert!(!socket.can_write());
+        let written = socket.get_ref().writer();
+        assert_eq!(written[..2], [0x88, 0x8f]);
+        assert_eq!(written[6..8], 1007u16.to_be_bytes());
+        assert_eq!(&written[8..], b"Invalid UTF-8");
+
+        // Without the option nothing is sent.
+        let incoming = Cursor::new(vec![0x81, 0x02, 0xff, 0xfe]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        assert!(matches!(socket.read(), Err(Error::Utf8)));
+        assert!(socket.can_write());
+        assert!(socket.get_ref().writer().is_empty());
+    }
+
+    #[test]
+    fn receive_malformed_close() {
+        let config = WebSocketConfig { close_on_error: true, ..WebSocketConfig::default() };
+        // A close payload must be empty or start with a 2 byte code.
+        let incoming = Cursor::new(vec![0x88, 0x01, 0x03]);
+        let mut socket =
+            WebSocket::from_read_write(incoming, Vec::new(), Role::Client, Some(config));
+        socket.set_masking_key_provider(|_| [0; 4]);
+        assert!(matches!(socket.read(), Err(Error::Protocol(ProtocolError::InvalidCloseSequence))));
+        assert_eq!(socket.get_ref().writer()[6..8], 1002u16.to_be_bytes());
+
+        // The reason must be valid UTF-8.
+        let incoming = Cursor::new(vec![0x88, 0x04, 0x03, 0xe8, 0xff, 0xfe]);
+        let mut socket =
+            WebSocket::from_read_write(incoming, Vec::new(), Role::Client, Some(config));
+        socket.set_masking_key_provider(|_| [0; 4]);
+        assert!(matches!(socket.read(), Err(Error::Utf8)));
+        assert_eq!(socket.get_ref().writer()[6..8], 1007u16.to_be_bytes());
+    }
+
+    #[test]
+    fn close_twice() {
+        let mut socket = WebSocket::from_read_write(io::empty(), Vec::new(), Role::Client, None);
+        socket.close(None).unwrap();
+        socket.close(Some(CloseFrame { code: CloseCode::Away, reason: "".into() })).unwrap();
+        // A single close frame without a status code.
+        assert_eq!(socket.get_ref().writer().len(), 6);
+        assert_eq!(socket.get_ref().writer()[..2], [0x88, 0x80]);
+    }
+
+    #[test]
+    fn read_batch() {
+        // Fails the test if the stream is read again after the first read.
+        struct Exhausted;
+
+        impl io::Read for Exhausted {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                panic!("read beyond the buffered data");
+            }
+        }
+
+        let incoming = io::Read::chain(
+            Cursor::new(vec![
+                0x81, 0x01, b'a', // text
+                0x89, 0x01, 0x07, // ping
+                0x81, 0x01, b'b', 0x81, 0x01, b'c', // two texts
+                0x88, 0x00, // close
+                0x81, 0x01, b'd', // after close
+            ]),
+            Exhausted,
+        );
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        assert_eq!(
+            socket.read_batch(2).unwrap(),
+            [Message::Text("a".into()), Message::Ping(vec![0x07])]
+        );
+        assert_eq!(
+            socket.read_batch(10).unwrap(),
+            [Message::Text("b".into()), Message::Text("c".into()), Message::Close(None)]
+        );
+        // The ping was answered, the reply to the close is queued like with `read`.
+        assert_eq!(socket.get_ref().writer()[..2], [0x8a, 0x81]);
+        socket.flush().unwrap();
+        assert_eq!(socket.get_ref().writer()[7..9], [0x88, 0x80]);
+        assert!(socket.read_batch(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_batch_error() {
+        let incoming = Cursor::new(vec![
+            0x81, 0x01, b'a', // text
+            0x83, 0x00, // reserved opcode
+        ]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        assert_eq!(socket.read_batch(10).unwrap(), [Message::Text("a".into())]);
+        assert!(matches!(
+            socket.read_batch(10),
+            Err(Error::Protocol(ProtocolError::InvalidOpcode(3)))
+        ));
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let config = WebSocketConfig {
+            idle_timeout: Some(Duration::from_secs(10)),
+            ..WebSocketConfig::default()
+        };
+        let incoming = Cursor::new(vec![0x89, 0x00]);
+        let mut socket =
+            WebSocket::from_read_write(incoming, Vec::new(), Role::Client, Some(config));
+        socket.set_masking_key_provider(|_| [0; 4]);
+        assert_eq!(socket.read().unwrap(), Message::Ping(vec![]));
+        socket.flush().unwrap();
+        let pong_len = socket.get_ref().writer().len();
+
+        // The ping counts as activity.
+        socket.tick(Instant::now() + Duration::from_secs(5)).unwrap();
+        assert_eq!(socket.get_ref().writer().len(), pong_len);
+
+        socket.tick(Instant::now() + Duration::from_secs(11)).unwrap();
+        let close = &socket.get_ref().writer()[pong_len..];
+        assert_eq!(close[..2], [0x88, 0x8e]);
+        assert_eq!(close[6..], *b"\x03\xe9Idle timeout");
+        assert!(!socket.can_write());
+
+        // Without a timeout nothing happens.
+        let mut socket = WebSocket::from_read_write(io::empty(), Vec::new(), Role::Client, None);
+        socket.tick(Instant::now() + Duration::from_secs(3600)).unwrap();
+        assert!(socket.get_ref().writer().is_empty());
+    }
+
+    #[test]
+    fn write_reserved_bits() {
+        let mut socket = WebSocket::from_read_write(io::empty(), Vec::new(), Role::Server, None);
+        let mut frame = Frame::message(b"ext".to_vec(), OpCode::Data(OpData::Binary), true);
+        frame.header_mut().rsv2 = true;
+        socket.send(Message::Frame(frame)).unwrap();
+
+        let written = socket.get_ref().writer().clone();
+        let frame = FrameSocket::new(Cursor::new(written)).read(None).unwrap().unwrap();
+        assert!(!frame.header().rsv1);
+        assert!(frame.header().rsv2);
+        assert!(!frame.header().rsv3);
+        assert_eq!(frame.payload(), b"ext");
+    }
+
+    #[test]
+    fn auto_close() {
+        // A close frame with code 1001 from the client.
+        let incoming = || Cursor::new(vec![0x88, 0x82, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe9]);
+        let away = Message::Close(Some(CloseFrame { code: CloseCode::Away, reason: "".into() }));
+
+        let mut socket = WebSocket::from_read_write(incoming(), Vec::new(), Role::Server, None);
+        assert_eq!(socket.read().unwrap(), away);
+        assert!(matches!(socket.read(), Err(Error::ConnectionClosed)));
+        // The close frame was echoed.
+        assert_eq!(socket.get_ref().writer(), &[0x88, 0x02, 0x03, 0xe9]);
+
+        let config = WebSocketConfig { auto_close: false, ..WebSocketConfig::default() };
+        let mut socket =
+            WebSocket::from_read_write(incoming(), Vec::new(), Role::Server, Some(config));
+        assert_eq!(socket.read().unwrap(), away);
+        assert!(matches!(
+            socket.send(Message::Text("late".into())),
+            Err(Error::Protocol(ProtocolError::SendAfterClosing))
+        ));
+        assert!(socket.get_ref().writer().is_empty());
+        let normal = CloseFrame { code: CloseCode::Normal, reason: "".into() };
+        assert!(matches!(socket.close(Some(normal)), Err(Error::ConnectionClosed)));
+        assert_eq!(socket.get_ref().writer(), &[0x88, 0x02, 0x03, 0xe8]);
+        assert!(matches!(socket.read(), Err(Error::AlreadyClosed)));
+
+        // The reply may be sent by reference too.
+        let incoming = Cursor::new(vec![0x88, 0x02, 0x03, 0xe9]);
+        let mut socket =
+            WebSocket::from_read_write(incoming, Vec::new(), Role::Client, Some(config));
+        socket.set_masking_key_provider(|_| [0; 4]);
+        assert_eq!(socket.read().unwrap(), away);
+        assert!(matches!(
+            socket.write_ref(&Message::Text("late".into())),
+            Err(Error::Protocol(ProtocolError::SendAfterClosing))
+        ));
+        let normal =
+            Message::Close(Some(CloseFrame { code: CloseCode::Normal, reason: "".into() }));
+        socket.write_ref(&normal).unwrap();
+        assert_eq!(socket.get_ref().writer(), &[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8]);
+    }
+
+    #[test]
+    fn send_reserved_close_code() {
+        for code in [0, 999, 1004, 1005, 1006, 1015, 2999, 5000, u16::MAX] {
+            let code = CloseCode::from(code);
+            let mut socket =
+                WebSocket::from_raw_socket(WriteMoc(Cursor::new(Vec::new())), Role::Client, None);
+            let frame = CloseFrame { code, reason: "".into() };
+            assert!(matches!(
+                socket.close(Some(frame)),
+                Err(Error::Protocol(ProtocolError::InvalidCloseCode(c))) if c == code
+            ));
+            assert!(socket.can_write());
+        }
+    }
+
     #[test]
     fn size_limiting_text_fragmented() {
         let incoming = Cursor::new(vec![
@@ -847,6 +2155,105 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn fragment_limiting() {
+        let mut incoming = vec![0x01, 0x01, b'a'];
+        for _ in 0..1_000_000 {
+            incoming.extend([0x00, 0x01, b'a']);
+        }
+        incoming.extend([0x80, 0x01, b'a']);
+        let limit = WebSocketConfig { max_fragments: Some(100), ..WebSocketConfig::default() };
+        let mut socket =
+            WebSocket::from_raw_socket(WriteMoc(Cursor::new(incoming)), Role::Client, Some(limit));
+
+        assert!(matches!(
+            socket.read(),
+            Err(Error::Capacity(CapacityError::TooManyFragments { max_fragments: 100 }))
+        ));
+        // Failed long before the whole input was consumed.
+        assert!(socket.get_ref().0.position() < 64 * 1024);
+    }
+
+    #[test]
+    fn reserved_bits() {
+        // No extension is ever negotiated, so RSV1, RSV2 and RSV3 must all be unset.
+        for rsv in [0x40, 0x20, 0x10] {
+            for opcode in [0x81, 0x82, 0x89] {
+                let incoming = Cursor::new(vec![opcode | rsv, 0x02, b'H', b'i']);
+                let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, None);
+                assert!(matches!(
+                    socket.read(),
+                    Err(Error::Protocol(ProtocolError::NonZeroReservedBits))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn interleaved_data_message() {
+        // A new text message may not start before the fragmented one is finished.
+        let incoming = Cursor::new(vec![0x01, 0x01, b'a', 0x81, 0x01, b'b']);
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, None);
+        assert!(matches!(
+            socket.read(),
+            Err(Error::Protocol(ProtocolError::ExpectedFragment(OpData::Text)))
+        ));
+
+        // Control frames may be interleaved though, and are returned before the message.
+        let incoming = Cursor::new(vec![
+            0x01, 0x01, b'a', 0x89, 0x01, 0x07, 0x8a, 0x00, 0x80, 0x01, b'b', 0x81, 0x01, b'c',
+        ]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        assert_eq!(socket.read().unwrap(), Message::Ping(vec![0x07]));
+        assert_eq!(socket.read().unwrap(), Message::Pong(vec![]));
+        assert_eq!(socket.read().unwrap(), Message::Text("ab".into()));
+        assert_eq!(socket.read().unwrap(), Message::Text("c".into()));
+        // The ping was answered while the message was incomplete.
+        assert_eq!(&socket.get_ref().writer()[..2], [0x8a, 0x81]);
+    }
+
+    #[test]
+    fn empty_fragments() {
+        let incoming = Cursor::new(vec![
+            0x01, 0x00, // empty first fragment
+            0x80, 0x02, b'H', b'i', // non-empty final fragment
+            0x01, 0x02, b'H', b'i', // non-empty first fragment
+            0x80, 0x00, // empty final fragment
+            0x01, 0x02, 0xc3, 0xa9, // "é"
+            0x00, 0x00, // empty continuation
+            0x80, 0x02, 0xc3, 0xa9, // "é"
+            0x02, 0x00, // empty binary message in two fragments
+            0x80, 0x00,
+        ]);
+        let limit = WebSocketConfig { max_message_size: Some(4), ..WebSocketConfig::default() };
+        let mut socket = WebSocket::from_raw_socket(WriteMoc(incoming), Role::Client, Some(limit));
+        assert_eq!(socket.read().unwrap(), Message::Text("Hi".into()));
+        assert_eq!(socket.read().unwrap(), Message::Text("Hi".into()));
+        assert_eq!(socket.read().unwrap(), Message::Text("éé".into()));
+        assert_eq!(socket.read().unwrap(), Message::Binary(vec![]));
+    }
+
+    #[test]
+    fn empty_messages() {
+        let mut socket = WebSocket::from_read_write(io::empty(), Vec::new(), Role::Server, None);
+        socket.send(Message::Text(String::new())).unwrap();
+        socket.send(Message::Binary(Vec::new())).unwrap();
+        socket.send(Message::Text("Hi".into())).unwrap();
+        let written = socket.get_ref().writer().clone();
+        assert_eq!(written[..4], [0x81, 0x00, 0x82, 0x00]);
+
+        let mut socket =
+            WebSocket::from_raw_socket(WriteMoc(Cursor::new(written.clone())), Role::Client, None);
+        assert_eq!(socket.read().unwrap(), Message::Text(String::new()));
+        assert_eq!(socket.read().unwrap(), Message::Binary(Vec::new()));
+        assert_eq!(socket.read().unwrap(), Message::Text("Hi".into()));
+
+        let skip = WebSocketConfig { skip_empty_messages: true, ..WebSocketConfig::default() };
+        let mut socket =
+            WebSocket::from_raw_socket(WriteMoc(Cursor::new(written)), Role::Client, Some(skip));
+        assert_eq!(socket.read().unwrap(), Message::Text("Hi".into()));
+    }
+
     #[test]
     fn size_limiting_binary() {
         let incoming = Cursor::new(vec![0x82, 0x03, 0x01, 0x02, 0x03]);
@@ -858,4 +2265,69 @@ mod tests {
             Err(Error::Capacity(CapacityError::MessageTooLong { size: 3, max_size: 2 }))
         ));
     }
+
+    #[test]
+    fn close_timeout() {
+        // A peer that swallows our close frame and never answers.
+        struct SilentPeer;
+
+        impl io::Read for SilentPeer {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "nothing to read"))
+            }
+        }
+
+        impl io::Write for SilentPeer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let config = WebSocketConfig {
+            close_timeout: Some(Duration::from_millis(50)),
+            ..WebSocketConfig::default()
+        };
+        let mut socket = WebSocket::from_raw_socket(SilentPeer, Role::Client, Some(config));
+        socket.close(None).unwrap();
+
+        assert!(
+            matches!(socket.read(), Err(Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock)
+        );
+        socket.tick(Instant::now()).unwrap();
+        let later = Instant::now() + Duration::from_millis(60);
+        assert!(matches!(socket.tick(later), Err(Error::ConnectionClosed)));
+        assert!(matches!(socket.read(), Err(Error::AlreadyClosed)));
+
+        // Also enforced by `read`, here with a timeout that elapses right away.
+        let config = WebSocketConfig { close_timeout: Some(Duration::ZERO), ..config };
+        let mut socket = WebSocket::from_raw_socket(SilentPeer, Role::Client, Some(config));
+        socket.close(None).unwrap();
+        assert!(matches!(socket.read(), Err(Error::ConnectionClosed)));
+        assert!(matches!(socket.read(), Err(Error::AlreadyClosed)));
+    }
+
+    #[test]
+    fn close_timeout_after_reply() {
+        let config = WebSocketConfig {
+            close_timeout: Some(Duration::from_millis(50)),
+            auto_close: false,
+            ..WebSocketConfig::default()
+        };
+        let incoming = Cursor::new(vec![0x88, 0x00]);
+        let mut socket =
+            WebSocket::from_read_write(incoming, Vec::new(), Role::Client, Some(config));
+        assert_eq!(socket.read().unwrap(), Message::Close(None));
+
+        // Nothing was sent yet, so there is nothing the peer could be late to answer.
+        let later = Instant::now() + Duration::from_secs(60);
+        socket.tick(later).unwrap();
+
+        socket.close(None).unwrap();
+        socket.tick(Instant::now()).unwrap();
+        let later = Instant::now() + Duration::from_millis(60);
+        assert!(matches!(socket.tick(later), Err(Error::ConnectionClosed)));
+    }
 }
@@ -0,0 +1,232 @@
+//! Reading a single message frame by frame.
+
+use std::{
+    io::{self, Read, Write},
+    mem, str,
+};
+
+use super::{
+    frame::coding::{Data as OpData, OpCode},
+    Message, WebSocket,
+};
+use crate::error::{Error, ProtocolError, Result};
+
+/// A reader over the payload of a single data message.
+///
+/// Created by [`WebSocket::read_stream`]. Payload bytes are yielded as the frames of the
+/// message arrive and the reader reaches EOF after the final frame. Control frames received
+/// in between are handled as usual, e.g. pings are answered automatically.
+///
+/// Text payloads are checked to be valid UTF-8 as they are read.
+#[derive(Debug)]
+pub struct MessageReader<'a, Stream> {
+    socket: &'a mut WebSocket<Stream>,
+    /// Whether the message is a text message.
+    text: bool,
+    /// Payload of the current frame.
+    payload: Vec<u8>,
+    /// Number of bytes of `payload` already read.
+    pos: usize,
+    /// Whether `payload` is the last part of the message.
+    fin: bool,
+    /// Trailing bytes of an UTF-8 sequence split between frames.
+    utf8_tail: Vec<u8>,
+}
+
+impl<'a, Stream: Read + Write> MessageReader<'a, Stream> {
+    /// Read the first frame of the next data message.
+    pub(super) fn new(socket: &'a mut WebSocket<Stream>) -> Result<Self> {
+        let (opcode, payload, fin) = loop {
+            let abandoned = socket.context.streaming;
+            let (opcode, payload, fin) = next_fragment(socket)?;
+            // Skip the rest of a message whose reader was dropped before its end.
+            if !(abandoned && opcode == OpData::Continue) {
+                break (opcode, payload, fin);
+            }
+        };
+        let text = match opcode {
+            OpData::Text => true,
+            OpData::Binary => false,
+            c => return Err(Error::Protocol(ProtocolError::ExpectedFragment(c))),
+        };
+        let mut reader =
+            MessageReader { socket, text, payload: Vec::new(), pos: 0, fin, utf8_tail: Vec::new() };
+        reader.set_payload(payload)?;
+        Ok(reader)
+    }
+
+    /// Indicates whether the message is a text message.
+    pub fn is_text(&self) -> bool {
+        self.text
+    }
+
+    /// Indicates whether the message is a binary message.
+    pub fn is_binary(&self) -> bool {
+        !self.text
+    }
+
+    /// Read the next frame of the message into `payload`.
+    fn fill(&mut self) -> Result<()> {
+        let (opcode, payload, fin) = next_fragment(self.socket)?;
+        if opcode != OpData::Continue {
+            return Err(Error::Protocol(ProtocolError::ExpectedFragment(opcode)));
+        }
+        self.fin = fin;
+        self.set_payload(payload)
+    }
+
+    fn set_payload(&mut self, payload: Vec<u8>) -> Result<()> {
+        if self.text {
+            let mut joined;
+            let data = if self.utf8_tail.is_empty() {
+                &payload
+            } else {
+                joined = mem::take(&mut self.utf8_tail);
+                joined.extend_from_slice(&payload);
+                &joined
+            };
+            match str::from_utf8(data) {
+                Ok(_) => {}
+                // The frame ends in the middle of a character.
+                Err(e) if e.error_len().is_none() && !self.fin => {
+                    self.utf8_tail = data[e.valid_up_to()..].to_vec();
+                }
+                Err(_) => return Err(Error::Utf8),
+            }
+        }
+        self.payload = payload;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<Stream: Read + Write> Read for MessageReader<'_, Stream> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.payload.len() {
+            if self.fin || buf.is_empty() {
+                return Ok(0);
+            }
+            self.fill().map_err(|err| match err {
+                Error::Io(err) => err,
+                err => io::Error::new(io::ErrorKind::Other, err),
+            })?;
+        }
+        let n = (&self.payload[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Read the next data frame, skipping control messages.
+fn next_fragment<Stream: Read + Write>(
+    socket: &mut WebSocket<Stream>,
+) -> Result<(OpData, Vec<u8>, bool)> {
+    loop {
+        match socket.context.read_fragment(&mut socket.socket)? {
+            Message::Frame(frame) => {
+                let opcode = match frame.header().opcode {
+                    OpCode::Data(data) => data,
+                    OpCode::Control(_) => panic!("Bug: control frame is not handled"),
+                };
+                let fin = frame.header().is_final;
+                return Ok((opcode, frame.into_data(), fin));
+            }
+            // The rest of a message that was partially read before streaming started.
+            Message::Text(text) => return Ok((OpData::Text, text.into(), true)),
+            Message::Binary(data) => return Ok((OpData::Binary, data, true)),
+            Message::Ping(_) | Message::Pong(_) => {}
+            Message::Close(_) => {
+                // Send the reply, the message is never going to be completed.
+                socket.flush()?;
+                return Err(Error::ConnectionClosed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        protocol::{Message, Role, WebSocket},
+    };
+
+    use std::io::{self, Cursor, Read};
+
+    #[test]
+    fn stream_fragments() {
+        let incoming = Cursor::new(vec![
+            0x02, 0x02, 0x01, 0x02, // first fragment
+            0x89, 0x01, 0x09, // ping in between
+            0x00, 0x01, 0x03, // continuation
+            0x80, 0x02, 0x04, 0x05, // final fragment
+            0x81, 0x02, b'H', b'i', // next message
+        ]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        let mut reader = socket.read_stream().unwrap();
+        assert!(reader.is_binary());
+        let mut payload = Vec::new();
+        io::copy(&mut reader, &mut payload).unwrap();
+        assert_eq!(payload, [1, 2, 3, 4, 5]);
+        // The ping was answered.
+        assert_eq!(&socket.get_ref().writer()[..2], [0x8a, 0x81]);
+        assert_eq!(socket.read().unwrap(), Message::Text("Hi".into()));
+    }
+
+    #[test]
+    fn stream_text() {
+        let incoming = Cursor::new(vec![0x01, 0x01, 0xc3, 0x80, 0x01, 0xa9]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        let mut reader = socket.read_stream().unwrap();
+        assert!(reader.is_text());
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "é");
+
+        // Character cut off by the end of the message.
+        let incoming = Cursor::new(vec![0x01, 0x01, 0xc3, 0x80, 0x00]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        let mut reader = socket.read_stream().unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn drop_reader() {
+        let incoming = Cursor::new(vec![
+            0x02, 0x01, 0x01, 0x00, 0x01, 0x02, 0x80, 0x01, 0x03, // dropped message
+            0x81, 0x02, b'H', b'i',
+        ]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        let mut reader = socket.read_stream().unwrap();
+        let mut byte = [0];
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [1]);
+        drop(reader);
+        assert_eq!(socket.read().unwrap(), Message::Text("Hi".into()));
+    }
+
+    #[test]
+    fn drop_reader_then_stream() {
+        let incoming = Cursor::new(vec![
+            0x02, 0x01, 0x01, 0x00, 0x01, 0x02, 0x80, 0x01, 0x03, // dropped message
+            0x81, 0x02, b'H', b'i',
+        ]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        let mut reader = socket.read_stream().unwrap();
+        let mut byte = [0];
+        reader.read_exact(&mut byte).unwrap();
+        drop(reader);
+        let mut text = String::new();
+        socket.read_stream().unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "Hi");
+    }
+
+    #[test]
+    fn close_instead_of_message() {
+        let incoming = Cursor::new(vec![0x88, 0x00]);
+        let mut socket = WebSocket::from_read_write(incoming, Vec::new(), Role::Client, None);
+        assert!(matches!(socket.read_stream(), Err(Error::ConnectionClosed)));
+        // The close frame was answered.
+        assert_eq!(&socket.get_ref().writer()[..2], [0x88, 0x80]);
+    }
+}
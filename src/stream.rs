@@ -5,13 +5,15 @@
 //! `Read + Write` traits.
 
 #[cfg(feature = "__rustls-tls")]
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "tcp-keepalive")]
+use std::time::Duration;
 use std::{
     fmt::{self, Debug},
     io::{Read, Result as IoResult, Write},
 };
 
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
 
 #[cfg(feature = "native-tls")]
 use native_tls_crate::TlsStream;
@@ -27,6 +29,73 @@ pub enum Mode {
     Tls,
 }
 
+/// TCP keepalive parameters, see [`WebSocketConfig::tcp_keepalive`].
+///
+/// Parameters left as `None` keep the system defaults. Not every platform supports every
+/// parameter, unsupported ones are ignored:
+///
+/// * `time` is supported everywhere except OpenBSD, which only has a system-wide setting.
+/// * `interval` is supported on Linux, Android, macOS, iOS, tvOS, watchOS, Windows, FreeBSD,
+///   NetBSD, DragonFly BSD, Fuchsia and illumos.
+/// * `retries` is supported on the same platforms as `interval` except Windows.
+///
+/// [`WebSocketConfig::tcp_keepalive`]: crate::protocol::WebSocketConfig::tcp_keepalive
+#[cfg(feature = "tcp-keepalive")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    /// How long the connection has to be idle before the first keepalive probe is sent.
+    pub time: Option<Duration>,
+    /// The time between keepalive probes.
+    pub interval: Option<Duration>,
+    /// The number of unanswered probes after which the connection is dropped.
+    pub retries: Option<u32>,
+}
+
+#[cfg(feature = "tcp-keepalive")]
+impl TcpKeepalive {
+    /// Enable keepalive with these parameters on `stream`.
+    pub(crate) fn apply(&self, stream: &TcpStream) -> IoResult<()> {
+        let mut params = socket2::TcpKeepalive::new();
+        if let Some(time) = self.time {
+            params = params.with_time(time);
+        }
+        #[cfg(any(
+            target_os = "android",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "fuchsia",
+            target_os = "illumos",
+            target_os = "ios",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "windows",
+        ))]
+        if let Some(interval) = self.interval {
+            params = params.with_interval(interval);
+        }
+        #[cfg(any(
+            target_os = "android",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "fuchsia",
+            target_os = "illumos",
+            target_os = "ios",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+            target_os = "tvos",
+            target_os = "watchos",
+        ))]
+        if let Some(retries) = self.retries {
+            params = params.with_retries(retries);
+        }
+        socket2::SockRef::from(stream).set_tcp_keepalive(&params)
+    }
+}
+
 /// Trait to switch TCP_NODELAY.
 pub trait NoDelay {
     /// Set the TCP_NODELAY option to the given value.
@@ -58,6 +127,101 @@ where
     }
 }
 
+/// Trait to shut down the writing half of a stream.
+pub trait ShutdownWrite {
+    /// Shut down the writing half of the stream, telling the peer no more data will be sent.
+    fn shutdown_write(&mut self) -> IoResult<()>;
+}
+
+impl ShutdownWrite for TcpStream {
+    fn shutdown_write(&mut self) -> IoResult<()> {
+        TcpStream::shutdown(self, Shutdown::Write)
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl<S: Read + Write + ShutdownWrite> ShutdownWrite for TlsStream<S> {
+    fn shutdown_write(&mut self) -> IoResult<()> {
+        TlsStream::shutdown(self)?;
+        self.get_mut().shutdown_write()
+    }
+}
+
+#[cfg(feature = "__rustls-tls")]
+impl<S, SD, T> ShutdownWrite for StreamOwned<S, T>
+where
+    S: DerefMut<Target = rustls::ConnectionCommon<SD>>,
+    SD: rustls::SideData,
+    T: Read + Write + ShutdownWrite,
+{
+    fn shutdown_write(&mut self) -> IoResult<()> {
+        self.conn.send_close_notify();
+        self.flush()?;
+        self.sock.shutdown_write()
+    }
+}
+
+/// A stream made of separate reading and writing halves, e.g. the two ends of a pipe pair.
+#[derive(Debug)]
+pub struct ReadWrite<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R, W> ReadWrite<R, W> {
+    /// Join `reader` and `writer` into a single stream.
+    pub fn new(reader: R, writer: W) -> Self {
+        ReadWrite { reader, writer }
+    }
+
+    /// Returns a shared reference to the reading half.
+    pub fn reader(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the reading half.
+    pub fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns a shared reference to the writing half.
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the writing half.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Split the stream into its reading and writing halves.
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<R: Read, W> Read for ReadWrite<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R, W: Write> Write for ReadWrite<R, W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.writer.flush()
+    }
+}
+
+impl<R, W: ShutdownWrite> ShutdownWrite for ReadWrite<R, W> {
+    fn shutdown_write(&mut self) -> IoResult<()> {
+        self.writer.shutdown_write()
+    }
+}
+
 /// A stream that might be protected with TLS.
 #[non_exhaustive]
 #[allow(clippy::large_enum_variant)]
@@ -99,6 +263,83 @@ impl<S: Read + Write + Debug> Debug for MaybeTlsStream<S> {
     }
 }
 
+/// Parameters of an established TLS session, see [`MaybeTlsStream::tls_info`].
+#[cfg(any(feature = "native-tls", feature = "__rustls-tls"))]
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The negotiated protocol version as sent on the wire, e.g. `0x0304` for TLS 1.3.
+    ///
+    /// Always `None` with `native-tls`, which does not expose it.
+    pub protocol_version: Option<u16>,
+    /// The IANA identifier of the negotiated cipher suite, e.g. `0x1302` for
+    /// `TLS_AES_256_GCM_SHA384`.
+    ///
+    /// Always `None` with `native-tls`, which does not expose it.
+    pub cipher_suite: Option<u16>,
+    /// The DER encoded end-entity certificate presented by the peer.
+    pub peer_certificate: Option<Vec<u8>>,
+}
+
+impl<S: Read + Write> MaybeTlsStream<S> {
+    /// Returns a shared reference to the underlying stream, with or without TLS.
+    ///
+    /// This allows setting socket options such as TCP keepalive on the connection.
+    pub fn get_ref(&self) -> &S {
+        match *self {
+            MaybeTlsStream::Plain(ref s) => s,
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(ref s) => s.get_ref(),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::Rustls(ref s) => s.get_ref(),
+        }
+    }
+
+    /// Returns a mutable reference to the underlying stream, with or without TLS.
+    ///
+    /// Reading from or writing to it directly corrupts the TLS session, if any.
+    pub fn get_mut(&mut self) -> &mut S {
+        match *self {
+            MaybeTlsStream::Plain(ref mut s) => s,
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(ref mut s) => s.get_mut(),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::Rustls(ref mut s) => s.get_mut(),
+        }
+    }
+}
+
+#[cfg(any(feature = "native-tls", feature = "__rustls-tls"))]
+impl<S: Read + Write> MaybeTlsStream<S> {
+    /// Returns the parameters of the TLS session, or `None` for a plain stream.
+    ///
+    /// With `native-tls` only the peer certificate is available.
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match *self {
+            MaybeTlsStream::Plain(_) => None,
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(ref s) => Some(TlsInfo {
+                peer_certificate: s
+                    .peer_certificate()
+                    .ok()
+                    .flatten()
+                    .and_then(|cert| cert.to_der().ok()),
+                ..TlsInfo::default()
+            }),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::Rustls(ref s) => Some(TlsInfo {
+                protocol_version: s.conn.protocol_version().map(u16::from),
+                cipher_suite: s.conn.negotiated_cipher_suite().map(|c| u16::from(c.suite())),
+                peer_certificate: s
+                    .conn
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .map(|cert| cert.to_vec()),
+            }),
+        }
+    }
+}
+
 impl<S: Read + Write> Read for MaybeTlsStream<S> {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
         match *self {
@@ -144,3 +385,39 @@ impl<S: Read + Write + NoDelay> NoDelay for MaybeTlsStream<S> {
         }
     }
 }
+
+impl<S: Read + Write + ShutdownWrite> ShutdownWrite for MaybeTlsStream<S> {
+    fn shutdown_write(&mut self) -> IoResult<()> {
+        match *self {
+            MaybeTlsStream::Plain(ref mut s) => s.shutdown_write(),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(ref mut s) => s.shutdown_write(),
+            #[cfg(feature = "__rustls-tls")]
+            MaybeTlsStream::Rustls(ref mut s) => s.shutdown_write(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "native-tls", feature = "__rustls-tls"))]
+    #[test]
+    fn tls_info_plain() {
+        use super::MaybeTlsStream;
+        use std::io::Cursor;
+
+        let stream = MaybeTlsStream::Plain(Cursor::new(Vec::<u8>::new()));
+        assert_eq!(stream.tls_info(), None);
+    }
+
+    #[cfg(feature = "__rustls-tls")]
+    #[test]
+    fn rustls_wire_values() {
+        use rustls::{CipherSuite, ProtocolVersion};
+
+        // The values documented for `TlsInfo`, which reports them as sent on the wire.
+        assert_eq!(u16::from(ProtocolVersion::TLSv1_2), 0x0303);
+        assert_eq!(u16::from(ProtocolVersion::TLSv1_3), 0x0304);
+        assert_eq!(u16::from(CipherSuite::TLS13_AES_256_GCM_SHA384), 0x1302);
+    }
+}
@@ -15,6 +15,7 @@ use crate::{
 /// `Plain` variant.
 #[non_exhaustive]
 #[allow(missing_debug_implementations)]
+#[derive(Clone)]
 pub enum Connector {
     /// Plain (non-TLS) connector.
     Plain,
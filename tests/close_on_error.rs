@@ -0,0 +1,115 @@
+//! Verifies that a close frame with a matching code is sent when the peer violates the protocol
+//! and `close_on_error` is enabled, as the Autobahn TestSuite expects.
+
+use std::io::Cursor;
+
+use tungstenite::{
+    error::{CapacityError, ProtocolError},
+    protocol::{Role, WebSocketConfig},
+    Error, WebSocket,
+};
+
+/// Feeds `incoming` to a server and returns the read error along with what the server sent.
+fn server_reply(incoming: Vec<u8>, config: WebSocketConfig) -> (Error, Vec<u8>) {
+    let mut socket =
+        WebSocket::from_read_write(Cursor::new(incoming), Vec::new(), Role::Server, Some(config));
+    let err = loop {
+        match socket.read() {
+            Ok(_) => {}
+            Err(err) => break err,
+        }
+    };
+    (err, socket.get_ref().writer().clone())
+}
+
+fn strict() -> WebSocketConfig {
+    WebSocketConfig { close_on_error: true, ..WebSocketConfig::default() }
+}
+
+/// Masked (with a zero key) client frame.
+fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![first, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn close_code(reply: &[u8]) -> u16 {
+    assert_eq!(reply[0], 0x88, "expected a close frame");
+    u16::from_be_bytes([reply[2], reply[3]])
+}
+
+#[test]
+fn invalid_utf8() {
+    // Case 6.3.1: invalid UTF-8 split over fragments.
+    let mut incoming = frame(0x01, &[0xce, 0xba, 0xe1]);
+    incoming.extend(frame(0x80, &[0xbd, 0xb9, 0xcf, 0x83, 0xce, 0xbc, 0xce, 0xb5, 0xed, 0xa0]));
+    let (err, reply) = server_reply(incoming, strict());
+    assert!(matches!(err, Error::Utf8));
+    assert_eq!(close_code(&reply), 1007);
+}
+
+#[test]
+fn reserved_opcode() {
+    // Case 4.1.1: non-control opcode 3.
+    let (err, reply) = server_reply(frame(0x83, b""), strict());
+    assert!(matches!(err, Error::Protocol(ProtocolError::InvalidOpcode(3))));
+    assert_eq!(close_code(&reply), 1002);
+}
+
+#[test]
+fn fragmented_ping() {
+    // Case 5.1: ping split into two fragments.
+    let mut incoming = frame(0x09, b"frag");
+    incoming.extend(frame(0x80, b"ment"));
+    let (err, reply) = server_reply(incoming, strict());
+    assert!(matches!(err, Error::Protocol(ProtocolError::FragmentedControlFrame)));
+    assert_eq!(close_code(&reply), 1002);
+}
+
+#[test]
+fn unexpected_continuation() {
+    // Case 5.9: continuation without a message to continue.
+    let (err, reply) = server_reply(frame(0x80, b"fragment"), strict());
+    assert!(matches!(err, Error::Protocol(ProtocolError::UnexpectedContinueFrame)));
+    assert_eq!(close_code(&reply), 1002);
+}
+
+#[test]
+fn interleaved_message() {
+    // A new message starts before the fragmented one is finished.
+    let mut incoming = frame(0x01, b"frag");
+    incoming.extend(frame(0x81, b"new"));
+    let (err, reply) = server_reply(incoming, strict());
+    assert!(matches!(err, Error::Protocol(ProtocolError::ExpectedFragment(_))));
+    assert_eq!(close_code(&reply), 1002);
+}
+
+#[test]
+fn reserved_bits() {
+    // Case 3.1: RSV1 set without an extension.
+    let (err, reply) = server_reply(frame(0xc1, b"Hello"), strict());
+    assert!(matches!(err, Error::Protocol(ProtocolError::NonZeroReservedBits)));
+    assert_eq!(close_code(&reply), 1002);
+}
+
+#[test]
+fn frame_too_big() {
+    let config = WebSocketConfig { max_frame_size: Some(4), ..strict() };
+    let (err, reply) = server_reply(frame(0x82, b"Hello"), config);
+    assert!(matches!(err, Error::Capacity(CapacityError::MessageTooLong { .. })));
+    assert_eq!(close_code(&reply), 1009);
+}
+
+#[test]
+fn disabled_by_default() {
+    let (err, reply) = server_reply(frame(0x83, b""), WebSocketConfig::default());
+    assert!(matches!(err, Error::Protocol(ProtocolError::InvalidOpcode(3))));
+    assert!(reply.is_empty());
+}
+
+#[test]
+fn reset_is_not_answered() {
+    let (err, reply) = server_reply(Vec::new(), strict());
+    assert!(matches!(err, Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)));
+    assert!(reply.is_empty());
+}
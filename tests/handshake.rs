@@ -134,11 +134,12 @@ fn test_request_single_subprotocol() {
     server_thread(3016, Some(vec!["my-sub-protocol".to_string()]));
     sleep(Duration::from_secs(1));
 
-    let (_, response) = connect(create_http_request(
+    let (websocket, response) = connect(create_http_request(
         "ws://127.0.0.1:3016",
         Some(vec!["my-sub-protocol".to_string()]),
     ))
     .unwrap();
+    assert_eq!(websocket.subprotocol(), Some("my-sub-protocol"));
 
     assert_eq!(
         response.headers().get("Sec-WebSocket-Protocol").unwrap(),
@@ -0,0 +1,38 @@
+//! Verifies that `connect` enables the configured TCP keepalive on the client socket.
+
+#![cfg(all(feature = "tcp-keepalive", target_os = "linux"))]
+
+use std::{net::TcpListener, thread::spawn, time::Duration};
+
+use socket2::SockRef;
+use tungstenite::{
+    accept, client::connect_with_config, protocol::WebSocketConfig, stream::TcpKeepalive,
+};
+
+#[test]
+fn keepalive_on_connect() {
+    env_logger::try_init().ok();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        accept(stream).unwrap();
+    });
+
+    let keepalive = TcpKeepalive {
+        time: Some(Duration::from_secs(60)),
+        interval: Some(Duration::from_secs(10)),
+        retries: Some(3),
+    };
+    let config = WebSocketConfig::builder().with_tcp_keepalive(Some(keepalive)).build();
+    let (socket, _) =
+        connect_with_config(format!("ws://127.0.0.1:{port}/socket"), Some(config), 3).unwrap();
+    server.join().unwrap();
+
+    let sock = SockRef::from(socket.get_ref().get_ref());
+    assert!(sock.keepalive().unwrap());
+    assert_eq!(sock.keepalive_time().unwrap(), Duration::from_secs(60));
+    assert_eq!(sock.keepalive_interval().unwrap(), Duration::from_secs(10));
+    assert_eq!(sock.keepalive_retries().unwrap(), 3);
+}